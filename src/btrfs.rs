@@ -0,0 +1,178 @@
+use crate::string::CStr;
+
+const BTRFS_IOCTL_MAGIC: libc::c_ulong = 0x94;
+
+const BTRFS_PATH_NAME_MAX: usize = 4087;
+
+const BTRFS_SUBVOL_NAME_MAX: usize = 4039;
+
+const BTRFS_SUBVOL_RDONLY: u64 = 1 << 1;
+
+#[cfg(any(
+    target_arch = "powerpc",
+    target_arch = "powerpc64",
+    target_arch = "mips",
+    target_arch = "mips64",
+    target_arch = "sparc",
+    target_arch = "sparc64"
+))]
+const fn ioc_write(nr: libc::c_ulong, size: usize) -> libc::c_ulong {
+    (4 << 29) | ((size as libc::c_ulong) << 16) | (BTRFS_IOCTL_MAGIC << 8) | nr
+}
+
+#[cfg(not(any(
+    target_arch = "powerpc",
+    target_arch = "powerpc64",
+    target_arch = "mips",
+    target_arch = "mips64",
+    target_arch = "sparc",
+    target_arch = "sparc64"
+)))]
+const fn ioc_write(nr: libc::c_ulong, size: usize) -> libc::c_ulong {
+    (1 << 30) | ((size as libc::c_ulong) << 16) | (BTRFS_IOCTL_MAGIC << 8) | nr
+}
+
+/// struct btrfs_ioctl_vol_args from linux/btrfs.h
+#[repr(C)]
+struct VolArgs {
+    fd: i64,
+    name: [libc::c_char; BTRFS_PATH_NAME_MAX + 1],
+}
+
+/// struct btrfs_ioctl_vol_args_v2 from linux/btrfs.h
+#[repr(C)]
+struct VolArgsV2 {
+    fd: i64,
+    transid: u64,
+    flags: u64,
+    unused: [u64; 4],
+    name: [libc::c_char; BTRFS_SUBVOL_NAME_MAX + 1],
+}
+
+const BTRFS_IOC_SNAP_DESTROY: libc::c_ulong = ioc_write(15, core::mem::size_of::<VolArgs>());
+
+const BTRFS_IOC_SNAP_CREATE_V2: libc::c_ulong = ioc_write(23, core::mem::size_of::<VolArgsV2>());
+
+/// Split an absolute path into the (NUL-terminated) parent directory and the last component.
+fn split_parent(path: &str) -> Result<(CStr, &str), libc::c_int> {
+    let path = path.trim_end_matches('/');
+
+    let Some(idx) = path.rfind('/') else {
+        return Err(libc::EINVAL);
+    };
+
+    let name = &path[idx + 1..];
+    if name.is_empty() || name == "." || name == ".." {
+        return Err(libc::EINVAL);
+    }
+
+    let parent = match idx {
+        0 => CStr::new("/")?,
+        _ => CStr::new(&path[..idx])?,
+    };
+
+    Ok((parent, name))
+}
+
+fn open_directory(path: &CStr) -> Result<libc::c_int, libc::c_int> {
+    let fd = unsafe {
+        libc::open(
+            path.inner(),
+            libc::O_RDONLY | libc::O_DIRECTORY | libc::O_CLOEXEC,
+        )
+    };
+
+    match fd < 0 {
+        true => Err(unsafe { *libc::__errno_location() }),
+        false => Ok(fd),
+    }
+}
+
+fn copy_name(dst: &mut [libc::c_char], name: &str) -> Result<(), libc::c_int> {
+    if name.len() >= dst.len() {
+        return Err(libc::ENAMETOOLONG);
+    }
+
+    for (d, s) in dst.iter_mut().zip(name.bytes()) {
+        *d = s as libc::c_char;
+    }
+
+    Ok(())
+}
+
+/**
+ * Delete the btrfs subvolume (or snapshot) at the given absolute path.
+ *
+ * The subvolume must not contain other subvolumes.
+ */
+pub fn delete_subvolume(path: &str) -> Result<(), libc::c_int> {
+    let (parent, name) = split_parent(path)?;
+
+    let mut args: VolArgs = unsafe { core::mem::zeroed() };
+    copy_name(&mut args.name, name)?;
+
+    let parent_fd = open_directory(&parent)?;
+
+    let result = unsafe {
+        /*
+         * On success, zero is returned. On error, -1 is returned, and errno
+         * is set to indicate the error.
+         */
+        match libc::ioctl(parent_fd, BTRFS_IOC_SNAP_DESTROY as _, &args) {
+            0 => Ok(()),
+            _ => Err(*libc::__errno_location()),
+        }
+    };
+
+    unsafe { libc::close(parent_fd) };
+
+    result
+}
+
+/**
+ * Create a snapshot of the subvolume at source as the new subvolume at the absolute path dest.
+ *
+ * Both paths must reside on the same btrfs filesystem and the parent of dest must exist.
+ *
+ * @param source the subvolume to be snapshotted
+ * @param dest where the snapshot will be created
+ * @param read_only when true the snapshot is created read-only
+ */
+pub fn create_snapshot(source: &str, dest: &str, read_only: bool) -> Result<(), libc::c_int> {
+    let (parent, name) = split_parent(dest)?;
+
+    let mut args: VolArgsV2 = unsafe { core::mem::zeroed() };
+    copy_name(&mut args.name, name)?;
+    if read_only {
+        args.flags |= BTRFS_SUBVOL_RDONLY;
+    }
+
+    let source_fd = open_directory(&CStr::new(source)?)?;
+    args.fd = source_fd as i64;
+
+    let parent_fd = match open_directory(&parent) {
+        Ok(fd) => fd,
+        Err(err) => {
+            unsafe { libc::close(source_fd) };
+            return Err(err);
+        }
+    };
+
+    let result = unsafe {
+        /*
+         * On success, zero is returned. On error, -1 is returned, and errno
+         * is set to indicate the error.
+         */
+        match libc::ioctl(parent_fd, BTRFS_IOC_SNAP_CREATE_V2 as _, &args) {
+            0 => Ok(()),
+            _ => Err(*libc::__errno_location()),
+        }
+    };
+
+    unsafe {
+        libc::close(parent_fd);
+        libc::close(source_fd);
+    }
+
+    result
+}
@@ -0,0 +1,208 @@
+use crate::string::CStr;
+use crate::vector::Vec;
+
+/// A program to be execve'd, with its argument vector and environment.
+#[derive(Default)]
+pub struct Command {
+    /// NUL-terminated arguments, stored back to back: the first one is the program
+    argv: Vec<u8>,
+    /// NUL-terminated name=value variables, stored back to back
+    envp: Vec<u8>,
+}
+
+/// Iterate over the strings of a buffer of NUL-terminated strings.
+fn strings(buf: &Vec<u8>) -> impl Iterator<Item = &[u8]> {
+    let slice = buf.as_slice().unwrap_or(&[]);
+
+    slice
+        .strip_suffix(&[0])
+        .unwrap_or(slice)
+        .split(|byte| *byte == 0)
+        .filter(move |_| !slice.is_empty())
+}
+
+fn push_string(buf: &mut Vec<u8>, string: &[u8]) -> Result<(), libc::c_int> {
+    if string.contains(&0) {
+        return Err(libc::EINVAL);
+    }
+
+    for byte in string.iter() {
+        buf.push(*byte)?;
+    }
+
+    buf.push(0)
+}
+
+/**
+ * Build an array of pointers to the strings of the given buffer, terminated by NULL,
+ * as expected by execve(2).
+ */
+fn pointers(buf: &Vec<u8>) -> Result<Vec<*const libc::c_char>, libc::c_int> {
+    let mut pointers = Vec::<*const libc::c_char>::default();
+
+    for string in strings(buf) {
+        pointers.push(string.as_ptr() as *const libc::c_char)?;
+    }
+
+    pointers.push(core::ptr::null())?;
+
+    Ok(pointers)
+}
+
+impl Command {
+    /// A command running the given program without arguments.
+    pub fn new(program: &str) -> Result<Self, libc::c_int> {
+        let mut command = Self::default();
+        command.arg(program.as_bytes())?;
+
+        Ok(command)
+    }
+
+    /**
+     * Parse a command line such as /lib/systemd/systemd --switched-root --system.
+     *
+     * Arguments are separated by whitespaces. As in a shell, single quotes preserve
+     * every character they enclose, while inside double quotes and outside quotes
+     * a backslash preserves the next character: '--unit=a b', "--unit=a b" and
+     * --unit=a\ b are the same argument.
+     */
+    pub fn parse(line: &str) -> Result<Self, libc::c_int> {
+        let mut command = Self::default();
+        let mut quote: Option<u8> = None;
+        let mut escaped = false;
+        let mut in_arg = false;
+
+        for byte in line.bytes() {
+            match (quote, byte) {
+                (_, 0) => return Err(libc::EINVAL),
+                _ if escaped => {
+                    command.argv.push(byte)?;
+                    escaped = false;
+                }
+                (Some(b'\''), b'\'') | (Some(b'"'), b'"') => quote = None,
+                (Some(b'\''), _) => command.argv.push(byte)?,
+                (_, b'\\') => {
+                    escaped = true;
+                    in_arg = true;
+                }
+                (None, b'\'' | b'"') => {
+                    quote = Some(byte);
+                    in_arg = true;
+                }
+                (None, b' ' | b'\t' | b'\n' | b'\r') => {
+                    if in_arg {
+                        command.argv.push(0)?;
+                        in_arg = false;
+                    }
+                }
+                _ => {
+                    command.argv.push(byte)?;
+                    in_arg = true;
+                }
+            }
+        }
+
+        if quote.is_some() || escaped {
+            return Err(libc::EINVAL);
+        }
+
+        if in_arg {
+            command.argv.push(0)?;
+        }
+
+        match command.argv.empty() {
+            true => Err(libc::EINVAL),
+            false => Ok(command),
+        }
+    }
+
+    pub fn arg(&mut self, arg: &[u8]) -> Result<(), libc::c_int> {
+        push_string(&mut self.argv, arg)
+    }
+
+    /// Add a variable given as name=value to the environment.
+    pub fn env(&mut self, variable: &[u8]) -> Result<(), libc::c_int> {
+        push_string(&mut self.envp, variable)
+    }
+
+    /// Whether the environment defines the given variable.
+    pub fn has_env(&self, name: &str) -> bool {
+        strings(&self.envp).any(|variable| {
+            variable
+                .strip_prefix(name.as_bytes())
+                .is_some_and(|rest| rest.first() == Some(&b'='))
+        })
+    }
+
+    /**
+     * Append the arguments this process was started with, but its name: for PID 1
+     * these are the kernel command line arguments meant for init (e.g. those after --).
+     */
+    pub fn forward_arguments(&mut self) -> Result<(), libc::c_int> {
+        let cmdline =
+            crate::read_whole_file(crate::SELF_CMDLINE_PATH, crate::SELF_CMDLINE_MAX_FILE_SIZE)?;
+
+        for arg in strings(&cmdline).skip(1) {
+            self.arg(arg)?;
+        }
+
+        Ok(())
+    }
+
+    /**
+     * Copy the environment this process was started with: for PID 1 these are HOME,
+     * TERM and the name=value kernel command line parameters the kernel does not know.
+     * PATH and TERM are given a default value when missing.
+     */
+    pub fn inherit_environment(&mut self) -> Result<(), libc::c_int> {
+        let environ =
+            crate::read_whole_file(crate::SELF_ENVIRON_PATH, crate::SELF_ENVIRON_MAX_FILE_SIZE)?;
+
+        for variable in strings(&environ) {
+            self.env(variable)?;
+        }
+
+        for (name, default) in [
+            ("PATH", crate::INIT_DEFAULT_PATH),
+            ("TERM", crate::INIT_DEFAULT_TERM),
+        ] {
+            if !self.has_env(name) {
+                self.env(default.as_bytes())?;
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn program(&self) -> &str {
+        strings(&self.argv)
+            .next()
+            .and_then(|program| core::str::from_utf8(program).ok())
+            .unwrap_or("")
+    }
+
+    /**
+     * Replace the current process with the command.
+     *
+     * Only returns on failure.
+     */
+    pub fn execute(&self) -> Result<(), libc::c_int> {
+        let program = CStr::new(self.program())?;
+        let argv = pointers(&self.argv)?;
+        let envp = pointers(&self.envp)?;
+
+        unsafe {
+            /*
+             * On success, execve() does not return, on error -1 is returned, and errno
+             * is set to indicate the error.
+             */
+            libc::execve(
+                program.inner(),
+                argv.as_slice().unwrap().as_ptr(),
+                envp.as_slice().unwrap().as_ptr(),
+            );
+
+            Err(*libc::__errno_location())
+        }
+    }
+}
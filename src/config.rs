@@ -1,135 +1,1447 @@
-use crate::mount::{MountFlag, Mountpoint, MountpointFlags};
+use crate::idmap::IdMap;
+use crate::link::{create_hardlink, create_symlink, is_symlink};
+use crate::mkdir::{create_directories, create_file, Owner};
+use crate::mount::{
+    direct_detach, set_tree_flags, MountError, MountFlag, Mountpoint, MountpointFlags,
+};
+use crate::string::{unescape_octal, CStr};
 use crate::vector::Vec;
 
+/// Maximum nesting of include directives.
+const MAX_INCLUDE_DEPTH: usize = 16;
+
+/**
+ * Values available to ${...} substitutions in rdtab entries.
+ */
+pub struct Context<'a> {
+    rdname: Option<&'a str>,
+    cmdline: Option<&'a str>,
+}
+
+pub struct Entry {
+    mountpoint: Mountpoint,
+    options: EntryOptions,
+    required_paths: Vec<CStr>,
+    /// File and line the entry was declared at, to report errors found after parsing.
+    path: Option<CStr>,
+    line: usize,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum LinkKind {
+    Symbolic,
+    Hard,
+}
+
+/// A link to be created inside the staged root before switching to it.
+pub struct Link {
+    kind: LinkKind,
+    target: CStr,
+    path: CStr,
+    required_paths: Vec<CStr>,
+}
+
+#[derive(Default)]
 pub struct Config {
-    mounts: Vec<Mountpoint>,
+    entries: Vec<Entry>,
+    links: Vec<Link>,
+}
+
+/// Why the configuration was rejected.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum ParseErrorReason {
+    /// A system call or an allocation failed with the given errno.
+    System(libc::c_int),
+    InvalidUtf8,
+    MissingColumns,
+    TooManyColumns,
+    RelativeTarget,
+    DuplicateTarget,
+    UnknownOption,
+    InvalidOptionValue,
+    ConflictingOptions,
+    InvalidReset,
+    InvalidCondition,
+    InvalidVariable,
+    InvalidLink,
+    IncludeLoop,
+    CircularDependency,
+}
+
+/**
+ * An error in the configuration, located by file, line and column when it
+ * originates from a specific line. Lines and columns are counted from 1,
+ * columns in bytes; 0 means unknown.
+ */
+pub struct ParseError {
+    path: Option<CStr>,
+    line: usize,
+    column: usize,
+    reason: ParseErrorReason,
+    /// The include lines that led to the file, innermost first.
+    included_from: Vec<(CStr, usize)>,
+}
+
+/// Options that are consumed by atomrootfsinit itself and never reach the kernel.
+#[derive(Copy, Clone, Debug, PartialEq, Default)]
+struct EntryOptions {
+    reset: bool,
+    nofail: bool,
+    required: bool,
+    device_timeout: libc::c_uint,
+    mkdir: Option<libc::mode_t>,
+    mkfile: Option<libc::mode_t>,
+    owner: Owner,
+    recursive_attr: bool,
+    idmap: Option<IdMap>,
+}
+
+impl ParseErrorReason {
+    /// NUL-terminated description, to be printed on the console.
+    fn description(&self) -> &'static [u8] {
+        match self {
+            Self::System(_) => b"system error\0",
+            Self::InvalidUtf8 => b"invalid UTF-8\0",
+            Self::MissingColumns => b"missing columns: source, target and type are required\0",
+            Self::TooManyColumns => b"too many columns\0",
+            Self::RelativeTarget => b"the target is not an absolute path\0",
+            Self::DuplicateTarget => b"the target is already declared in this file\0",
+            Self::UnknownOption => b"unknown x- option\0",
+            Self::InvalidOptionValue => b"invalid option value\0",
+            Self::ConflictingOptions => b"conflicting options\0",
+            Self::InvalidReset => b"x-reset needs an overlay or a bind mount of an absolute path, not / or an ancestor of the staged root\0",
+            Self::InvalidCondition => b"invalid condition\0",
+            Self::InvalidVariable => b"undefined variable or unterminated ${\0",
+            Self::InvalidLink => b"expected link <target> <path> symlink|hardlink\0",
+            Self::IncludeLoop => b"include loop or includes nested too deep\0",
+            Self::CircularDependency => b"circular mount dependency\0",
+        }
+    }
+}
+
+impl From<libc::c_int> for ParseError {
+    fn from(err: libc::c_int) -> Self {
+        Self::new(ParseErrorReason::System(err))
+    }
+}
+
+impl ParseError {
+    fn new(reason: ParseErrorReason) -> Self {
+        Self {
+            path: None,
+            line: 0,
+            column: 0,
+            reason,
+            included_from: Vec::default(),
+        }
+    }
+
+    /// Set the column, unless a more precise one is already known.
+    fn at(mut self, column: usize) -> Self {
+        if !self.located() && self.column == 0 {
+            self.column = column;
+        }
+
+        self
+    }
+
+    /// Make the column relative to a line that starts the given number of bytes earlier.
+    fn shifted(mut self, offset: usize) -> Self {
+        if self.path.is_none() && self.column > 0 {
+            self.column += offset;
+        }
+
+        self
+    }
+
+    fn located(&self) -> bool {
+        self.path.is_some()
+    }
+
+    fn locate(&mut self, path: &str, line: usize) {
+        self.path = CStr::new(path).ok();
+        self.line = line;
+    }
+
+    pub fn path(&self) -> Option<&str> {
+        self.path.as_ref().map(|path| path.as_str())
+    }
+
+    pub fn line(&self) -> usize {
+        self.line
+    }
+
+    pub fn column(&self) -> usize {
+        self.column
+    }
+
+    pub fn reason(&self) -> ParseErrorReason {
+        self.reason
+    }
+
+    pub fn errno(&self) -> libc::c_int {
+        match self.reason {
+            ParseErrorReason::System(err) => err,
+            ParseErrorReason::IncludeLoop | ParseErrorReason::CircularDependency => libc::ELOOP,
+            _ => libc::EINVAL,
+        }
+    }
+
+    /**
+     * Print the error on the console as <file>:<line>:<column>: <reason> (<errno>),
+     * preceded by the include lines that led to the file.
+     */
+    pub fn print(&self) {
+        let description = self.reason.description().as_ptr() as *const libc::c_char;
+
+        for (path, line) in self.included_from.iter() {
+            unsafe {
+                libc::printf(
+                    b"In file included from %s at line %u\n\0".as_ptr() as *const libc::c_char,
+                    path.inner(),
+                    *line as libc::c_uint,
+                );
+            }
+        }
+
+        match &self.path {
+            Some(path) => unsafe {
+                libc::printf(
+                    b"%s:%u:%u: %s (%d)\n\0".as_ptr() as *const libc::c_char,
+                    path.inner(),
+                    self.line as libc::c_uint,
+                    self.column as libc::c_uint,
+                    description,
+                    self.errno(),
+                );
+            },
+            None => unsafe {
+                libc::printf(
+                    b"Invalid configuration: %s (%d)\n\0".as_ptr() as *const libc::c_char,
+                    description,
+                    self.errno(),
+                );
+            },
+        }
+    }
+}
+
+/// 1-based column at which field, a slice of line, starts.
+fn column_of(line: &str, field: &str) -> usize {
+    (field.as_ptr() as usize).saturating_sub(line.as_ptr() as usize) + 1
+}
+
+/**
+ * Strip a comment from a line: as in fstab a # starts a comment only
+ * at the beginning of a field, otherwise it is part of the field.
+ */
+fn strip_comment(line: &str) -> &str {
+    let mut previous = ' ';
+
+    for (idx, ch) in line.char_indices() {
+        if ch == '#' && previous.is_ascii_whitespace() {
+            return &line[..idx];
+        }
+
+        previous = ch;
+    }
+
+    line
+}
+
+/**
+ * Decode the fstab escapes of a field, e.g. \040 for a space, \011 for a tab,
+ * \134 for a backslash and \043 for a #.
+ */
+fn unescape<'b>(field: &str, buf: &'b mut Vec<u8>) -> Result<&'b str, ParseError> {
+    unescape_octal(field, buf)?;
+
+    core::str::from_utf8(buf.as_slice().unwrap_or(&[]))
+        .map_err(|_| ParseError::new(ParseErrorReason::InvalidUtf8))
+}
+
+/// Parse the optional octal mode of x-mkdir[=mode] and x-mkfile[=mode].
+fn parse_mode(value: Option<&str>, default: libc::mode_t) -> Result<libc::mode_t, ParseError> {
+    match value {
+        Some(mode) => libc::mode_t::from_str_radix(mode, 8)
+            .ok()
+            .filter(|mode| *mode <= 0o7777)
+            .ok_or(ParseError::new(ParseErrorReason::InvalidOptionValue)),
+        None => Ok(default),
+    }
+}
+
+/// The propagation type set by an option, and whether it applies recursively.
+fn propagation_flag(flag: &str) -> Option<(MountFlag, bool)> {
+    let (flag, recursive) = match flag.strip_prefix('r') {
+        Some(flag) => (flag, true),
+        None => (flag, false),
+    };
+
+    match flag {
+        "shared" => Some((MountFlag::Shared, recursive)),
+        "private" => Some((MountFlag::Private, recursive)),
+        "slave" => Some((MountFlag::Slave, recursive)),
+        "unbindable" => Some((MountFlag::Unbindable, recursive)),
+        _ => None,
+    }
 }
 
 fn serialized_flags_split(
     serialized_flags: &str,
-) -> Result<(MountpointFlags, Vec<u8>), libc::c_int> {
+) -> Result<(MountpointFlags, Vec<u8>, EntryOptions), ParseError> {
     let mut flags = MountpointFlags::default();
     let mut data = Vec::<u8>::default();
+    let mut options = EntryOptions::default();
+    let mut read_only = false;
+    let mut read_write = false;
+    let mut propagation: Option<MountFlag> = None;
 
     for flag in serialized_flags.split(',') {
         match flag {
-            "rw" => {}
+            "" => {}
+            "x-reset" => {
+                options.reset = true;
+            }
+            "nofail" => {
+                options.nofail = true;
+            }
+            "x-recursive-attr" => {
+                options.recursive_attr = true;
+            }
+            "x-atomrootfsinit.required" => {
+                options.required = true;
+            }
+            flg if flg.starts_with("x-atomrootfsinit.device-timeout=") => {
+                options.device_timeout = flg["x-atomrootfsinit.device-timeout=".len()..]
+                    .parse::<libc::c_uint>()
+                    .map_err(|_| ParseError::new(ParseErrorReason::InvalidOptionValue))?;
+            }
+            flg if flg == "x-mkdir" || flg.starts_with("x-mkdir=") => {
+                options.mkdir = Some(parse_mode(flg.strip_prefix("x-mkdir="), 0o755)?);
+            }
+            flg if flg == "x-mkfile" || flg.starts_with("x-mkfile=") => {
+                options.mkfile = Some(parse_mode(flg.strip_prefix("x-mkfile="), 0o644)?);
+            }
+            flg if flg.starts_with("x-idmap=") => {
+                options.idmap = Some(
+                    IdMap::parse(&flg["x-idmap=".len()..])
+                        .map_err(|_| ParseError::new(ParseErrorReason::InvalidOptionValue))?,
+                );
+            }
+            flg if flg.starts_with("x-uid=") => {
+                options.owner.uid = Some(
+                    flg["x-uid=".len()..]
+                        .parse::<libc::uid_t>()
+                        .map_err(|_| ParseError::new(ParseErrorReason::InvalidOptionValue))?,
+                );
+            }
+            flg if flg.starts_with("x-gid=") => {
+                options.owner.gid = Some(
+                    flg["x-gid=".len()..]
+                        .parse::<libc::gid_t>()
+                        .map_err(|_| ParseError::new(ParseErrorReason::InvalidOptionValue))?,
+                );
+            }
+            // the util-linux option table: every flag can be negated, the last occurrence
+            // wins; only ro and rw given explicitly together are rejected as conflicting
+            "defaults" => {
+                // rw, suid, dev, exec, async: overrides the options before it
+                read_only = false;
+                read_write = false;
+                flags.clear(MountFlag::ReadOnly);
+                flags.clear(MountFlag::NoSUID);
+                flags.clear(MountFlag::NoDev);
+                flags.clear(MountFlag::NoExec);
+                flags.clear(MountFlag::Synchronous);
+            }
+            "ro" => {
+                read_only = true;
+                flags.set(MountFlag::ReadOnly);
+            }
+            "rw" => {
+                read_write = true;
+                flags.clear(MountFlag::ReadOnly);
+            }
             "nodev" => flags.set(MountFlag::NoDev),
+            "dev" => flags.clear(MountFlag::NoDev),
             "noexec" => flags.set(MountFlag::NoExec),
+            "exec" => flags.clear(MountFlag::NoExec),
             "nosuid" => flags.set(MountFlag::NoSUID),
+            "suid" => flags.clear(MountFlag::NoSUID),
+            "sync" => flags.set(MountFlag::Synchronous),
+            "async" => flags.clear(MountFlag::Synchronous),
+            "dirsync" => flags.set(MountFlag::DirSync),
+            "mand" => flags.set(MountFlag::MandatoryLock),
+            "nomand" => flags.clear(MountFlag::MandatoryLock),
             "noatime" => flags.set(MountFlag::NoAccessTime),
-            "remount" => flags.set(MountFlag::Remount),
-            "recursive" => flags.set(MountFlag::Recursive),
+            "atime" => flags.clear(MountFlag::NoAccessTime),
+            "nodiratime" => flags.set(MountFlag::NoDirAccessTime),
+            "diratime" => flags.clear(MountFlag::NoDirAccessTime),
+            "relatime" => flags.set(MountFlag::RelativeAccessTime),
+            "norelatime" => flags.clear(MountFlag::RelativeAccessTime),
+            "strictatime" => flags.set(MountFlag::StrictAccessTime),
+            "nostrictatime" => flags.clear(MountFlag::StrictAccessTime),
             "lazytime" => flags.set(MountFlag::Lazytime),
+            "nolazytime" => flags.clear(MountFlag::Lazytime),
+            "iversion" => flags.set(MountFlag::IVersion),
+            "noiversion" => flags.clear(MountFlag::IVersion),
+            "nosymfollow" => flags.set(MountFlag::NoSymFollow),
+            "symfollow" => flags.clear(MountFlag::NoSymFollow),
             "silent" => flags.set(MountFlag::Silent),
-            "shared" => flags.set(MountFlag::Shared),
-            "private" => flags.set(MountFlag::Private),
+            "loud" => flags.clear(MountFlag::Silent),
+            "remount" => flags.set(MountFlag::Remount),
+            // the raw MS_REC flag: applies both to the bind and to the propagation type
+            "recursive" => {
+                flags.set(MountFlag::Recursive);
+                flags.set(MountFlag::RecursivePropagation);
+            }
             "bind" => flags.set(MountFlag::Bind),
-            "ro" => flags.set(MountFlag::ReadOnly),
+            "rbind" => {
+                flags.set(MountFlag::Bind);
+                flags.set(MountFlag::Recursive);
+            }
+            // propagation types: the r-prefixed variants apply to the whole subtree
+            flg if propagation_flag(flg).is_some() => {
+                let (propagation_type, recursive) = propagation_flag(flg).unwrap();
+
+                if propagation.is_some_and(|previous| previous != propagation_type) {
+                    return Err(ParseError::new(ParseErrorReason::ConflictingOptions));
+                }
+
+                propagation = Some(propagation_type);
+                flags.set(propagation_type);
+                if recursive {
+                    flags.set(MountFlag::RecursivePropagation);
+                }
+            }
+            // only meaningful to mount(8) and to boot-time tooling: nothing to do here
+            "auto" | "noauto" | "user" | "nouser" | "users" | "owner" | "group" | "_netdev" => {}
+            flg if flg.starts_with("x-") => {
+                return Err(ParseError::new(ParseErrorReason::UnknownOption));
+            }
             flg => {
+                // everything else is filesystem-specific data
+                if !data.empty() {
+                    data.push(b',')?;
+                }
+
                 for d in flg.as_bytes().iter() {
                     data.push(*d)?;
                 }
             }
         }
+    }
 
-        data.push(b',')?;
+    // an ID mapping can only be set on a new mount, not on an existing one
+    if (options.mkdir.is_some() && options.mkfile.is_some())
+        || (read_write && read_only)
+        || (options.idmap.is_some() && flags.is_set(MountFlag::Remount))
+    {
+        return Err(ParseError::new(ParseErrorReason::ConflictingOptions));
     }
 
     // mount flags are given as C string to the kernel: ensure it is NULL-terminated
     if !data.empty() {
-        // remove the last (unused) ','
-        let _ = data.pop();
-
         // data can be a pointer to a kernel-defined struct,
         // but most filesystems in linux accepts a C-like string:
         // make sure such a string is NUL-terminated
         data.push(0u8)?;
     }
 
-    Ok((flags, data))
+    Ok((flags, data, options))
 }
 
-impl Config {
-    pub fn new(content: Vec<u8>) -> Result<Self, libc::c_int> {
-        let mut mounts = Vec::<Mountpoint>::default();
+impl<'a> Context<'a> {
+    /**
+     * @param rdname the name of the deployment being booted, if any
+     * @param cmdline the kernel command line, if available
+     */
+    pub fn new(rdname: Option<&'a str>, cmdline: Option<&'a str>) -> Self {
+        Self { rdname, cmdline }
+    }
 
-        let raw_data = content.split(b'\n', false)?;
-        drop(content);
+    /**
+     * Value of a kernel command line parameter: an empty string for a parameter
+     * without a value. As for the kernel the last occurrence wins.
+     */
+    pub fn cmdline_param(&self, key: &str) -> Option<&'a str> {
+        self.cmdline?
+            .split_ascii_whitespace()
+            .filter_map(|param| match param.split_once('=') {
+                Some((name, value)) if name == key => Some(value),
+                None if param == key => Some(""),
+                _ => None,
+            })
+            .next_back()
+    }
 
-        for mount_entry_line in raw_data.iter() {
-            for (idx, unsplitted_line) in core::str::from_utf8(mount_entry_line.as_slice().unwrap())
-                .map_err(|_| libc::EINVAL)?
-                .split("#")
-                .enumerate()
+    /// Whether the parameter is on the command line:
+    /// in the key=value form its value must match too.
+    fn cmdline_matches(&self, condition: &str) -> bool {
+        match condition.split_once('=') {
+            Some((key, value)) => self.cmdline_param(key) == Some(value),
+            None => self.cmdline_param(condition).is_some(),
+        }
+    }
+
+    fn variable(&self, name: &str) -> Option<&'a str> {
+        match name {
+            "rdname" => self.rdname,
+            "sysroot" => Some(crate::SYSROOT),
+            "arch" => Some(crate::ARCH),
+            "partuuid" => self.cmdline_param("root")?.strip_prefix("PARTUUID="),
+            _ => self.cmdline_param(name.strip_prefix("cmdline:")?),
+        }
+    }
+
+    /**
+     * Copy input to output replacing every ${name} with the value of the variable,
+     * or with the given default in the ${name:-default} form.
+     *
+     * An undefined variable without a default or an unterminated ${ is an error.
+     */
+    fn expand(&self, input: &str, output: &mut Vec<u8>) -> Result<(), ParseError> {
+        let mut rest = input;
+
+        while let Some(start) = rest.find("${") {
+            for &ch in &rest.as_bytes()[..start] {
+                output.push(ch)?;
+            }
+
+            let after = &rest[start + 2..];
+            let end = after
+                .find('}')
+                .ok_or(ParseError::new(ParseErrorReason::InvalidVariable))?;
+
+            let value = match after[..end].split_once(":-") {
+                Some((name, default)) => self.variable(name).unwrap_or(default),
+                None => self
+                    .variable(&after[..end])
+                    .ok_or(ParseError::new(ParseErrorReason::InvalidVariable))?,
+            };
+
+            for &ch in value.as_bytes() {
+                output.push(ch)?;
+            }
+
+            rest = &after[end + 1..];
+        }
+
+        for &ch in rest.as_bytes() {
+            output.push(ch)?;
+        }
+
+        Ok(())
+    }
+
+    /// Expand input into the given buffer, returning the result as a string.
+    fn expanded<'b>(&self, input: &str, buf: &'b mut Vec<u8>) -> Result<&'b str, ParseError> {
+        self.expand(input, buf)?;
+
+        // input is valid UTF-8 and so are the variables: the result can't be invalid
+        Ok(core::str::from_utf8(buf.as_slice().unwrap_or(&[])).unwrap_or(""))
+    }
+
+    /// Decode the escapes of a field, then expand it into the given buffer.
+    fn expanded_field<'b>(&self, field: &str, buf: &'b mut Vec<u8>) -> Result<&'b str, ParseError> {
+        let mut unescaped_buf = Vec::<u8>::default();
+        let unescaped = unescape(field, &mut unescaped_buf)?;
+
+        self.expanded(unescaped, buf)
+    }
+
+    /**
+     * Decode the escapes of the options field, then expand every option on its own:
+     * a variable whose value contains a comma would inject further options.
+     */
+    fn expanded_options<'b>(
+        &self,
+        field: &str,
+        buf: &'b mut Vec<u8>,
+    ) -> Result<&'b str, ParseError> {
+        let mut unescaped_buf = Vec::<u8>::default();
+        let unescaped = unescape(field, &mut unescaped_buf)?;
+
+        for (idx, option) in unescaped.split(',').enumerate() {
+            if idx > 0 {
+                buf.push(b',')?;
+            }
+
+            let mut option_buf = Vec::<u8>::default();
+            let option = self.expanded(option, &mut option_buf)?;
+
+            if option.contains(',') {
+                return Err(ParseError::new(ParseErrorReason::InvalidOptionValue));
+            }
+
+            for &ch in option.as_bytes() {
+                buf.push(ch)?;
+            }
+        }
+
+        Ok(core::str::from_utf8(buf.as_slice().unwrap_or(&[])).unwrap_or(""))
+    }
+}
+
+/// Check a directory to be wiped: / and the ancestors of the staged root would
+/// take the whole early root with them.
+fn reset_path(dir: &str) -> Result<CStr, libc::c_int> {
+    match dir.trim_end_matches('/').is_empty() || path_contains(dir, crate::SYSROOT) {
+        true => Err(libc::EINVAL),
+        false => CStr::new(dir),
+    }
+}
+
+/// Collect the directories that a factory reset has to wipe for the given entry:
+/// the upper and work directories of an overlay or the source of a bind mount.
+fn reset_paths_of(
+    src: Option<&str>,
+    fstype: Option<&str>,
+    flags: &MountpointFlags,
+    serialized_flags: &str,
+    reset_paths: &mut Vec<CStr>,
+) -> Result<(), libc::c_int> {
+    if fstype == Some("overlay") {
+        let mut found = false;
+        for flag in serialized_flags.split(',') {
+            if let Some(dir) = flag
+                .strip_prefix("upperdir=")
+                .or_else(|| flag.strip_prefix("workdir="))
             {
-                if idx > 0 || unsplitted_line.is_empty() {
-                    break;
+                reset_paths.push(reset_path(dir)?)?;
+                found = true;
+            }
+        }
+
+        return match found {
+            true => Ok(()),
+            false => Err(libc::EINVAL),
+        };
+    }
+
+    match (flags.is_set(MountFlag::Bind), src) {
+        (true, Some(src)) if src.starts_with('/') => reset_paths.push(reset_path(src)?),
+        _ => Err(libc::EINVAL),
+    }
+}
+
+/**
+ * Parse a mount entry.
+ *
+ * @param declared_targets the targets already declared in the same file, or None
+ *        when the entry is an alternative picked by [if-exists=...] conditions
+ */
+fn parse_mount_entry(
+    line: &str,
+    context: &Context,
+    declared_targets: Option<&Vec<CStr>>,
+) -> Result<Entry, ParseError> {
+    let mut columns: [Option<&str>; 6] = [None; 6];
+
+    let mut index = 0;
+    for mount_entry_param in line.split_ascii_whitespace() {
+        if index >= columns.len() {
+            return Err(ParseError::new(ParseErrorReason::TooManyColumns)
+                .at(column_of(line, mount_entry_param)));
+        }
+
+        columns[index] = Some(mount_entry_param);
+        index += 1;
+    }
+
+    if index < 3 {
+        return Err(ParseError::new(ParseErrorReason::MissingColumns).at(line.len() + 1));
+    }
+
+    let column = |index: usize| columns[index].map_or(line.len() + 1, |col| column_of(line, col));
+
+    // escapes are decoded and variables expanded in source, target and options
+    // (one option at a time)
+    let mut src_buf = Vec::<u8>::default();
+    let src = Some(
+        context
+            .expanded_field(columns[0].unwrap(), &mut src_buf)
+            .map_err(|err| err.at(column(0)))?,
+    );
+
+    let mut target_buf = Vec::<u8>::default();
+    let target = context
+        .expanded_field(columns[1].unwrap(), &mut target_buf)
+        .map_err(|err| err.at(column(1)))?;
+
+    if !target.starts_with('/') {
+        return Err(ParseError::new(ParseErrorReason::RelativeTarget).at(column(1)));
+    }
+
+    if declared_targets.is_some_and(|declared_targets| {
+        declared_targets
+            .iter()
+            .any(|declared| declared.as_str() == target)
+    }) {
+        return Err(ParseError::new(ParseErrorReason::DuplicateTarget).at(column(1)));
+    }
+
+    let fstype = columns[2];
+
+    let mut serialized_flags_buf = Vec::<u8>::default();
+    let serialized_flags = context
+        .expanded_options(columns[3].unwrap_or(""), &mut serialized_flags_buf)
+        .map_err(|err| err.at(column(3)))?;
+
+    // dump and pass columns are accepted for fstab compatibility, but unused
+    let (flags, data, options) =
+        serialized_flags_split(serialized_flags).map_err(|err| err.at(column(3)))?;
+
+    if options.reset {
+        // only validate here: the paths are collected again when the reset is performed
+        reset_paths_of(
+            src,
+            fstype,
+            &flags,
+            serialized_flags,
+            &mut Vec::<CStr>::default(),
+        )
+        .map_err(|err| match err {
+            libc::EINVAL => ParseError::new(ParseErrorReason::InvalidReset).at(column(3)),
+            err => ParseError::from(err),
+        })?;
+    }
+
+    let mut mountpoint = Mountpoint::new(
+        src,
+        target,
+        fstype,
+        flags,
+        match data.empty() {
+            false => Some(data.as_slice().unwrap()),
+            true => None,
+        },
+    )?;
+
+    if let Some(idmap) = options.idmap {
+        mountpoint.set_idmap(idmap);
+    }
+
+    Ok(Entry {
+        mountpoint,
+        options,
+        required_paths: Vec::default(),
+        path: None,
+        line: 0,
+    })
+}
+
+/**
+ * Parse the arguments of a link line: <target> <path> symlink|hardlink
+ *
+ * Both paths refer to the staged root: the target of a symlink is stored as it is,
+ * while the target of a hardlink must be an absolute path.
+ */
+fn parse_link_entry(line: &str, context: &Context) -> Result<Link, ParseError> {
+    let mut columns: [&str; 3] = [""; 3];
+
+    let mut index = 0;
+    for link_entry_param in line.split_ascii_whitespace() {
+        if index >= columns.len() {
+            return Err(ParseError::new(ParseErrorReason::TooManyColumns)
+                .at(column_of(line, link_entry_param)));
+        }
+
+        columns[index] = link_entry_param;
+        index += 1;
+    }
+
+    if index < columns.len() {
+        return Err(ParseError::new(ParseErrorReason::MissingColumns).at(line.len() + 1));
+    }
+
+    let kind = match columns[2] {
+        "symlink" => LinkKind::Symbolic,
+        "hardlink" => LinkKind::Hard,
+        kind => {
+            return Err(ParseError::new(ParseErrorReason::InvalidLink).at(column_of(line, kind)))
+        }
+    };
+
+    let mut target_buf = Vec::<u8>::default();
+    let target = context
+        .expanded_field(columns[0], &mut target_buf)
+        .map_err(|err| err.at(column_of(line, columns[0])))?;
+
+    let mut path_buf = Vec::<u8>::default();
+    let path = context
+        .expanded_field(columns[1], &mut path_buf)
+        .map_err(|err| err.at(column_of(line, columns[1])))?;
+
+    if !path.starts_with('/') {
+        return Err(
+            ParseError::new(ParseErrorReason::RelativeTarget).at(column_of(line, columns[1]))
+        );
+    }
+
+    if kind == LinkKind::Hard && !target.starts_with('/') {
+        return Err(ParseError::new(ParseErrorReason::InvalidLink).at(column_of(line, columns[0])));
+    }
+
+    Ok(Link {
+        kind,
+        target: CStr::new(target)?,
+        path: CStr::new(path)?,
+        required_paths: Vec::default(),
+    })
+}
+
+/**
+ * Strip the [if-...] conditions prefixed to a line.
+ *
+ * Conditions on the command line and on the architecture are evaluated right away,
+ * while paths required by [if-exists=...] are collected, as devices may only appear
+ * once devtmpfs has been mounted.
+ *
+ * @return whether the immediate conditions hold, and the rest of the line
+ */
+fn parse_conditions<'l>(
+    mut line: &'l str,
+    context: &Context,
+    required_paths: &mut Vec<CStr>,
+) -> Result<(bool, &'l str), ParseError> {
+    let origin = line;
+    let mut satisfied = true;
+
+    while let Some(prefixed) = line.strip_prefix('[') {
+        let column = column_of(origin, line);
+        let invalid = || ParseError::new(ParseErrorReason::InvalidCondition).at(column);
+
+        let (condition, rest) = prefixed.split_once(']').ok_or_else(invalid)?;
+        line = rest.trim_start();
+
+        let (kind, value) = condition.split_once('=').ok_or_else(invalid)?;
+
+        let mut buf = Vec::<u8>::default();
+        let value = context
+            .expanded(value, &mut buf)
+            .map_err(|err| err.at(column))?;
+
+        match kind {
+            "if-cmdline" => satisfied &= context.cmdline_matches(value),
+            "if-arch" => satisfied &= value == crate::ARCH,
+            "if-exists" => required_paths.push(CStr::new(value)?)?,
+            _ => return Err(invalid()),
+        }
+    }
+
+    Ok((satisfied, line))
+}
+
+fn paths_exist(paths: &Vec<CStr>) -> bool {
+    paths
+        .iter()
+        .all(|path| crate::check_file_exists(path.as_str()).unwrap_or(false))
+}
+
+/// Identify a file by device and inode, so that the same file
+/// reached through different paths is detected.
+fn file_identity(path: &str) -> Result<(libc::dev_t, libc::ino_t), libc::c_int> {
+    let path_str = CStr::new(path)?;
+
+    let mut stat_buf: libc::stat = unsafe { core::mem::zeroed() };
+    if unsafe { libc::stat(path_str.inner(), &mut stat_buf) } != 0 {
+        return Err(unsafe { *libc::__errno_location() });
+    }
+
+    Ok((stat_buf.st_dev, stat_buf.st_ino))
+}
+
+/// Resolve an included path: relative paths are relative to the directory of the including file.
+fn include_path(including: &str, included: &str) -> Result<CStr, libc::c_int> {
+    if included.starts_with('/') {
+        return CStr::new(included);
+    }
+
+    match including.rfind('/') {
+        Some(idx) => CStr::concat(&[&including[..idx + 1], included]),
+        None => CStr::new(included),
+    }
+}
+
+/// Whether path is parent itself or lies below it. The root directory contains nothing:
+/// mounting over / hides the early root instead of providing a place for other mounts.
+fn path_contains(parent: &str, path: &str) -> bool {
+    let parent = parent.trim_end_matches('/');
+    let path = path.trim_end_matches('/');
+
+    !parent.is_empty()
+        && path
+            .strip_prefix(parent)
+            .is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+}
+
+/// Whether entry changes a mount made by another entry (a remount or a propagation
+/// change) rather than putting a mount at its target.
+fn modifies_mount(entry: &Entry) -> bool {
+    !entry.mountpoint.attaches_mount() && !entry.mountpoint.flags().is_set(MountFlag::Move)
+}
+
+/**
+ * Whether entry has to be mounted after other: either its target lies below the
+ * target of other, or one of its sources (the source of a bind mount, the lower,
+ * upper and work directories of an overlay) is provided by other.
+ *
+ * A remount or propagation change instead comes after every mount at or below its
+ * target, so that x-recursive-attr and recursive propagation reach all of them.
+ */
+fn depends_on(entry: &Entry, other: &Entry) -> bool {
+    let mountpoint = &entry.mountpoint;
+    let other_target = other.mountpoint.target();
+
+    if modifies_mount(other) {
+        return false;
+    } else if modifies_mount(entry) {
+        return path_contains(mountpoint.target(), other_target);
+    }
+
+    // entries for the same target (alternatives picked by [if-exists=...]) keep their order
+    if path_contains(other_target, mountpoint.target())
+        && !path_contains(mountpoint.target(), other_target)
+    {
+        return true;
+    }
+
+    if mountpoint.flags().is_set(MountFlag::Bind)
+        && mountpoint
+            .src()
+            .is_some_and(|src| path_contains(other_target, src))
+    {
+        return true;
+    }
+
+    if mountpoint.fstype() == Some("overlay") {
+        let data = mountpoint
+            .data()
+            .map(|data| core::str::from_utf8(data).unwrap_or(""))
+            .unwrap_or("")
+            .trim_end_matches('\0');
+
+        for option in data.split(',') {
+            let paths = option
+                .strip_prefix("lowerdir=")
+                .or_else(|| option.strip_prefix("upperdir="))
+                .or_else(|| option.strip_prefix("workdir="));
+
+            if paths.is_some_and(|paths| paths.split(':').any(|p| path_contains(other_target, p))) {
+                return true;
+            }
+        }
+    }
+
+    false
+}
+
+/// Collect the names of the drop-in fragments in the given directory, in lexical order.
+fn dropin_fragments(dir_path: &str) -> Result<Vec<CStr>, libc::c_int> {
+    let mut fragments = Vec::<CStr>::default();
+
+    let dir_path_str = CStr::new(dir_path)?;
+    let dir = unsafe { libc::opendir(dir_path_str.inner()) };
+    if dir.is_null() {
+        return match unsafe { *libc::__errno_location() } {
+            libc::ENOENT => Ok(fragments),
+            err => Err(err),
+        };
+    }
+
+    loop {
+        let entry = unsafe { libc::readdir(dir) };
+        if entry.is_null() {
+            break;
+        }
+
+        let name = unsafe { core::ffi::CStr::from_ptr((*entry).d_name.as_ptr()) };
+        let Ok(name) = name.to_str() else {
+            continue;
+        };
+
+        // hidden files are skipped, as editors and package managers leave temporary files around
+        if name.starts_with('.') || !name.ends_with(crate::RDTAB_DROPIN_SUFFIX) {
+            continue;
+        }
+
+        if let Err(err) = CStr::concat(&[dir_path, "/", name]).and_then(|f| fragments.push(f)) {
+            unsafe { libc::closedir(dir) };
+            return Err(err);
+        }
+    }
+
+    unsafe { libc::closedir(dir) };
+
+    if let Some(fragments) = fragments.as_mut_slice() {
+        fragments.sort_unstable_by(|a, b| a.as_str().cmp(b.as_str()));
+    }
+
+    Ok(fragments)
+}
+
+impl Entry {
+    pub fn mountpoint(&self) -> &Mountpoint {
+        &self.mountpoint
+    }
+
+    /// Whether a failure to mount this entry must not abort the boot:
+    /// x-atomrootfsinit.required takes precedence over nofail.
+    pub fn nofail(&self) -> bool {
+        self.options.nofail && !self.options.required
+    }
+
+    /// Seconds to wait for the source to appear before mounting.
+    pub fn device_timeout(&self) -> libc::c_uint {
+        self.options.device_timeout
+    }
+
+    /// Check the conditions that can only be evaluated right before mounting.
+    pub fn conditions_met(&self) -> bool {
+        paths_exist(&self.required_paths)
+    }
+
+    /**
+     * Mount the entry, creating its target first when requested.
+     *
+     * When a step after attaching the new mount fails, the mount is detached again:
+     * a failed entry never leaves a half configured mount behind.
+     *
+     * @param rootdev the device to be used in place of the rootdev source
+     */
+    pub fn mount(&self, rootdev: &Option<CStr>) -> Result<(), MountError> {
+        self.prepare_target().map_err(MountError::Target)?;
+
+        let result = self
+            .mountpoint
+            .mount(rootdev)
+            .and_then(|_| self.apply_recursive_attr());
+
+        if let Err(MountError::Remount(_) | MountError::Propagation(_)) = result {
+            if self.mountpoint.attaches_mount() {
+                let _ = direct_detach(self.mountpoint.target());
+            }
+        }
+
+        result
+    }
+
+    fn apply_recursive_attr(&self) -> Result<(), MountError> {
+        // with x-recursive-attr the flags reach the mounts below the target too, as the
+        // remount of a recursive bind already does: a remount is sorted after all of them
+        let flags = self.mountpoint.flags();
+        let recursive_bind = flags.is_set(MountFlag::Bind) && flags.is_set(MountFlag::Recursive);
+
+        if self.options.recursive_attr && !recursive_bind {
+            set_tree_flags(self.mountpoint.target(), &flags).map_err(MountError::Remount)?;
+        }
+
+        Ok(())
+    }
+
+    /**
+     * Create the mount target when requested by x-mkdir or x-mkfile,
+     * giving the newly created paths the ownership set by x-uid and x-gid.
+     */
+    fn prepare_target(&self) -> Result<(), libc::c_int> {
+        let target = self.mountpoint.target();
+
+        if let Some(mode) = self.options.mkdir {
+            create_directories(target, mode, self.options.owner)?;
+        }
+
+        if let Some(mode) = self.options.mkfile {
+            create_file(target, mode, self.options.owner)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Link {
+    pub fn kind(&self) -> LinkKind {
+        self.kind
+    }
+
+    pub fn target(&self) -> &str {
+        self.target.as_str()
+    }
+
+    pub fn path(&self) -> &str {
+        self.path.as_str()
+    }
+
+    /// Check the conditions that can only be evaluated right before creating the link.
+    pub fn conditions_met(&self) -> bool {
+        paths_exist(&self.required_paths)
+    }
+
+    /**
+     * Create the link inside the given root.
+     *
+     * An existing symlink at the same path is replaced, and an existing hardlink
+     * to the same file is kept, so that links on persistent storage survive reboots.
+     *
+     * @param root the staged root the paths refer to
+     */
+    pub fn create(&self, root: &str) -> Result<(), libc::c_int> {
+        let root = root.trim_end_matches('/');
+        let path = CStr::concat(&[root, self.path.as_str()])?;
+
+        match self.kind {
+            LinkKind::Symbolic => match create_symlink(self.target.as_str(), path.as_str()) {
+                Err(libc::EEXIST) if is_symlink(path.as_str()) => {
+                    crate::remove::remove_file(path.as_str())?;
+                    create_symlink(self.target.as_str(), path.as_str())
                 }
+                result => result,
+            },
+            LinkKind::Hard => {
+                let target = CStr::concat(&[root, self.target.as_str()])?;
 
-                let mut index = 0;
-                let mut src: Option<&str> = None;
-                let mut target: Option<&str> = None;
-                let mut fstype: Option<&str> = None;
-                let mut flags = MountpointFlags::default();
-                let mut data = Vec::default();
-                let mut _dump: libc::c_uint = 0;
-                let mut _fsck: libc::c_uint = 0;
-                for mount_entry_param in unsplitted_line.split(" ") {
-                    if mount_entry_param.is_empty() {
-                        continue;
+                match create_hardlink(target.as_str(), path.as_str()) {
+                    Err(libc::EEXIST)
+                        if file_identity(target.as_str())? == file_identity(path.as_str())? =>
+                    {
+                        Ok(())
                     }
+                    result => result,
+                }
+            }
+        }
+    }
+}
+
+impl Config {
+    /**
+     * Parse the rdtab at the given path, whose content has already been read,
+     * together with every file it includes and every drop-in fragment.
+     *
+     * Drop-in fragments are processed in lexical order after the main file:
+     * an entry whose target is already present replaces the previous one.
+     * Entries are then ordered by their dependencies.
+     *
+     * Parsing goes on after a malformed line, so that every error is returned.
+     */
+    pub fn new(path: &str, content: Vec<u8>, context: &Context) -> Result<Self, Vec<ParseError>> {
+        let mut config = Self::default();
+        let mut errors = Vec::<ParseError>::default();
+
+        match config.load(path, content, context, &mut errors) {
+            Ok(()) if errors.empty() => Ok(config),
+            Ok(()) => Err(errors),
+            Err(err) => {
+                let _ = errors.push(err);
+                Err(errors)
+            }
+        }
+    }
+
+    fn load(
+        &mut self,
+        path: &str,
+        content: Vec<u8>,
+        context: &Context,
+        errors: &mut Vec<ParseError>,
+    ) -> Result<(), ParseError> {
+        let mut includes = Vec::<(libc::dev_t, libc::ino_t)>::default();
+
+        if let Ok(identity) = file_identity(path) {
+            includes.push(identity)?;
+        }
 
-                    match index {
-                        0 => src = Some(mount_entry_param),
-                        1 => target = Some(mount_entry_param),
-                        2 => fstype = Some(mount_entry_param),
-                        3 => (flags, data) = serialized_flags_split(mount_entry_param)?,
-                        4 => {
-                            _dump = match mount_entry_param {
-                                "0" => 0,
-                                "1" => 1,
-                                "2" => 2,
-                                _ => 0,
-                            }
-                        }
-                        5 => {
-                            _fsck = match mount_entry_param {
-                                "0" => 0,
-                                "1" => 1,
-                                "2" => 2,
-                                _ => 0,
-                            }
-                        }
-                        _ => return Err(libc::EINVAL),
-                    };
-
-                    index += 1;
+        self.parse(path, content, context, &mut includes, errors)?;
+
+        let fragments = dropin_fragments(crate::RDTAB_DROPIN_PATH)?;
+        for fragment in fragments.iter() {
+            self.include(fragment.as_str(), context, &mut Vec::default(), errors)?;
+        }
+
+        // the dependencies of a configuration that is going to be rejected do not matter
+        match errors.empty() {
+            true => self.sort_entries(),
+            false => Ok(()),
+        }
+    }
+
+    /**
+     * Reorder the entries so that every entry is mounted after the ones it depends on.
+     *
+     * The sort is stable: entries without a dependency between them keep the order
+     * they were declared in. Circular dependencies are rejected.
+     */
+    fn sort_entries(&mut self) -> Result<(), ParseError> {
+        let Some(entries) = self.entries.as_mut_slice() else {
+            return Ok(());
+        };
+
+        for placed in 0..entries.len() {
+            let pending = &entries[placed..];
+
+            let dependency = |idx: usize| {
+                (0..pending.len()).find(|&other_idx| {
+                    other_idx != idx && depends_on(&pending[idx], &pending[other_idx])
+                })
+            };
+
+            // the first pending entry that does not depend on another pending one
+            let Some(ready) = (0..pending.len()).position(|idx| dependency(idx).is_none()) else {
+                // every pending entry depends on another one: following the dependencies
+                // as many times as there are entries ends up inside a cycle
+                let culprit = (0..pending.len()).fold(0, |idx, _| dependency(idx).unwrap_or(idx));
+                let culprit = &pending[culprit];
+
+                unsafe {
+                    libc::printf(
+                        b"Circular mount dependency involving %s\n\0".as_ptr()
+                            as *const libc::c_char,
+                        culprit.mountpoint.target().as_ptr() as *const libc::c_char,
+                    );
                 }
 
-                if index < 3 {
-                    return Err(libc::EINVAL);
+                let mut err = ParseError::new(ParseErrorReason::CircularDependency);
+                if let Some(path) = &culprit.path {
+                    err.locate(path.as_str(), culprit.line);
                 }
 
-                let mount = Mountpoint::new(
-                    src,
-                    target.unwrap(),
-                    fstype,
-                    flags,
-                    match data.empty() {
-                        false => Some(data.as_slice().unwrap()),
-                        true => None,
-                    },
-                )?;
+                return Err(err);
+            };
+
+            entries[placed..=placed + ready].rotate_right(1);
+        }
+
+        // no entry is placed before one it depends on, e.g. a remount before the mounts below it
+        debug_assert!((0..entries.len()).all(|idx| !entries[idx + 1..]
+            .iter()
+            .any(|later| depends_on(&entries[idx], later))));
+
+        Ok(())
+    }
+
+    fn include(
+        &mut self,
+        path: &str,
+        context: &Context,
+        includes: &mut Vec<(libc::dev_t, libc::ino_t)>,
+        errors: &mut Vec<ParseError>,
+    ) -> Result<(), ParseError> {
+        if includes.len() >= MAX_INCLUDE_DEPTH {
+            return Err(ParseError::new(ParseErrorReason::IncludeLoop));
+        }
+
+        let identity = file_identity(path)?;
+        if includes.find(identity).is_some() {
+            return Err(ParseError::new(ParseErrorReason::IncludeLoop));
+        }
+
+        let content = crate::read_whole_file(path, crate::RDTAB_MAX_FILE_SIZE)?;
+
+        includes.push(identity)?;
+        let result = self.parse(path, content, context, includes, errors);
+        let _ = includes.pop();
+
+        result
+    }
 
-                mounts.push(mount)?;
+    /**
+     * Parse the lines of a file, adding every error found, located by file and line,
+     * to errors and going on with the next line.
+     *
+     * An error is returned only when parsing cannot go on at all.
+     */
+    fn parse(
+        &mut self,
+        path: &str,
+        content: Vec<u8>,
+        context: &Context,
+        includes: &mut Vec<(libc::dev_t, libc::ino_t)>,
+        errors: &mut Vec<ParseError>,
+    ) -> Result<(), ParseError> {
+        let raw_data = content.split(b'\n', true)?;
+        drop(content);
+
+        // targets declared by this file, as opposed to the ones that can be overridden
+        let mut declared_targets = Vec::<CStr>::default();
+
+        for (line_idx, mount_entry_line) in raw_data.iter().enumerate() {
+            let reported = errors.len();
+
+            let result = match core::str::from_utf8(mount_entry_line.as_slice().unwrap_or(&[])) {
+                Ok(raw_line) => match strip_comment(raw_line).trim() {
+                    "" => Ok(()),
+                    line => self
+                        .parse_line(
+                            (path, line_idx + 1),
+                            line,
+                            context,
+                            includes,
+                            &mut declared_targets,
+                            errors,
+                        )
+                        .map_err(|err| err.shifted(column_of(raw_line, line) - 1)),
+                },
+                Err(err) => {
+                    Err(ParseError::new(ParseErrorReason::InvalidUtf8).at(err.valid_up_to() + 1))
+                }
+            };
+
+            // the errors added meanwhile come from an included file: record how it was reached
+            if let Some(included_errors) = errors.as_mut_slice() {
+                for err in &mut included_errors[reported..] {
+                    err.included_from.push((CStr::new(path)?, line_idx + 1))?;
+                }
+            }
+
+            if let Err(mut err) = result {
+                err.locate(path, line_idx + 1);
+                errors.push(err)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// @param (path, line_number) where the line comes from
+    fn parse_line(
+        &mut self,
+        (path, line_number): (&str, usize),
+        line: &str,
+        context: &Context,
+        includes: &mut Vec<(libc::dev_t, libc::ino_t)>,
+        declared_targets: &mut Vec<CStr>,
+        errors: &mut Vec<ParseError>,
+    ) -> Result<(), ParseError> {
+        let mut required_paths = Vec::<CStr>::default();
+        let (satisfied, entry_line) = parse_conditions(line, context, &mut required_paths)?;
+        if !satisfied {
+            return Ok(());
+        }
+
+        match entry_line.strip_prefix("include") {
+            Some(included) if included.starts_with([' ', '\t']) => {
+                // files are included while parsing: there is nothing to defer
+                if !paths_exist(&required_paths) {
+                    return Ok(());
+                }
+
+                let included = included.trim();
+                let column = column_of(line, included);
+
+                let mut included_buf = Vec::<u8>::default();
+                let included =
+                    unescape(included, &mut included_buf).map_err(|err| err.at(column))?;
+
+                let included = include_path(path, included)?;
+                self.include(included.as_str(), context, includes, errors)
+                    .map_err(|err| err.at(column))
+            }
+            _ => match entry_line.strip_prefix("link") {
+                Some(link) if link.starts_with([' ', '\t']) => {
+                    let mut link = parse_link_entry(link, context)
+                        .map_err(|err| err.shifted(column_of(line, link) - 1))?;
+                    link.required_paths = required_paths;
+                    Ok(self.add_link(link)?)
+                }
+                _ => {
+                    // several entries may provide the same target under different conditions
+                    let conditional = !required_paths.empty();
+
+                    let mut entry = parse_mount_entry(
+                        entry_line,
+                        context,
+                        (!conditional).then_some(&*declared_targets),
+                    )
+                    .map_err(|err| err.shifted(column_of(line, entry_line) - 1))?;
+                    entry.required_paths = required_paths;
+                    entry.path = CStr::new(path).ok();
+                    entry.line = line_number;
+
+                    if !conditional {
+                        declared_targets.push(CStr::new(entry.mountpoint.target())?)?;
+                    }
+
+                    Ok(self.add(entry)?)
+                }
+            },
+        }
+    }
+
+    /**
+     * Add an entry, replacing the one with the same target if there is one.
+     *
+     * Entries with [if-exists=...] conditions are alternatives picked right before
+     * mounting: they neither replace nor get replaced, as the condition may not hold.
+     */
+    fn add(&mut self, entry: Entry) -> Result<(), libc::c_int> {
+        if let Some(entries) = self.entries.as_mut_slice() {
+            if let Some(existing) = entries.iter_mut().find(|existing| {
+                entry.required_paths.empty()
+                    && existing.required_paths.empty()
+                    && existing.mountpoint.target() == entry.mountpoint.target()
+            }) {
+                *existing = entry;
+                return Ok(());
             }
         }
 
-        Ok(Self { mounts })
+        self.entries.push(entry)
     }
 
-    pub fn iter_mounts(&'_ self) -> crate::vector::VecIter<'_, Mountpoint> {
-        self.mounts.iter()
+    /// Add a link, replacing the one with the same path if there is one.
+    fn add_link(&mut self, link: Link) -> Result<(), libc::c_int> {
+        if let Some(links) = self.links.as_mut_slice() {
+            if let Some(existing) = links
+                .iter_mut()
+                .find(|existing| existing.path.as_str() == link.path.as_str())
+            {
+                *existing = link;
+                return Ok(());
+            }
+        }
+
+        self.links.push(link)
+    }
+
+    pub fn iter_mounts(&'_ self) -> impl Iterator<Item = &'_ Mountpoint> {
+        self.entries.iter().map(|entry| &entry.mountpoint)
+    }
+
+    pub fn iter_entries(&'_ self) -> crate::vector::VecIter<'_, Entry> {
+        self.entries.iter()
+    }
+
+    pub fn iter_links(&'_ self) -> crate::vector::VecIter<'_, Link> {
+        self.links.iter()
+    }
+
+    /// Collect the directories to be wiped by a factory reset.
+    pub fn reset_paths(&self) -> Result<Vec<CStr>, libc::c_int> {
+        let mut reset_paths = Vec::<CStr>::default();
+
+        // like the mount pass, entries whose conditions are not met are left alone
+        for entry in self
+            .entries
+            .iter()
+            .filter(|entry| entry.options.reset && entry.conditions_met())
+        {
+            let mountpoint = &entry.mountpoint;
+            let data = mountpoint
+                .data()
+                .map(|data| core::str::from_utf8(data).unwrap_or(""))
+                .unwrap_or("")
+                .trim_end_matches('\0');
+
+            reset_paths_of(
+                mountpoint.src(),
+                mountpoint.fstype(),
+                &mountpoint.flags(),
+                data,
+                &mut reset_paths,
+            )?;
+        }
+
+        Ok(reset_paths)
     }
 }
@@ -0,0 +1,205 @@
+use crate::{
+    remove::{remove_directory, remove_directory_contents},
+    string::CStr,
+};
+
+const PENDING: u8 = b'-';
+const DONE: u8 = b'+';
+const INVALID: u8 = b'!';
+
+/**
+ * A destructive operation requested by the running system and deferred to the next boot,
+ * when the involved deployments are guaranteed not to be in use.
+ */
+#[derive(Debug)]
+enum Operation<'a> {
+    DeleteDeployment(&'a str),
+    RenameDeployment(&'a str, &'a str),
+    DeleteSubvolume(&'a str),
+    Truncate(&'a str),
+}
+
+fn deployment_name(name: &str) -> Result<&str, libc::c_int> {
+    match name.is_empty() || name == "." || name == ".." || name.contains('/') {
+        true => Err(libc::EINVAL),
+        false => Ok(name),
+    }
+}
+
+fn absolute_path(path: &str) -> Result<&str, libc::c_int> {
+    match path.starts_with('/') && path != "/" {
+        true => Ok(path),
+        false => Err(libc::EINVAL),
+    }
+}
+
+impl<'a> Operation<'a> {
+    fn parse(line: &'a str) -> Result<Self, libc::c_int> {
+        let mut params = line.split_ascii_whitespace();
+
+        let op = params.next().ok_or(libc::EINVAL)?;
+        let first = params.next().ok_or(libc::EINVAL)?;
+        let second = params.next();
+
+        let operation = match (op, second) {
+            ("delete", None) => Self::DeleteDeployment(deployment_name(first)?),
+            ("rename", Some(second)) => {
+                Self::RenameDeployment(deployment_name(first)?, deployment_name(second)?)
+            }
+            ("delete-subvolume", None) => Self::DeleteSubvolume(absolute_path(first)?),
+            ("truncate", None) => Self::Truncate(absolute_path(first)?),
+            _ => return Err(libc::EINVAL),
+        };
+
+        match params.next() {
+            Some(_) => Err(libc::EINVAL),
+            None => Ok(operation),
+        }
+    }
+
+    /**
+     * Perform the operation: every operation is idempotent, so that a step
+     * interrupted before being marked as done can safely be repeated.
+     */
+    fn execute(&self) -> Result<(), libc::c_int> {
+        match self {
+            Self::DeleteDeployment(name) => {
+                let path = CStr::concat(&[crate::DEPLOYMENTS_PATH, "/", name])?;
+                remove_directory_contents(path.as_str(), true)?;
+                match remove_directory(path.as_str()) {
+                    Err(libc::ENOENT) => Ok(()),
+                    result => result,
+                }
+            }
+            Self::RenameDeployment(from, to) => {
+                let from = CStr::concat(&[crate::DEPLOYMENTS_PATH, "/", from])?;
+                let to = CStr::concat(&[crate::DEPLOYMENTS_PATH, "/", to])?;
+
+                if unsafe { libc::rename(from.inner(), to.inner()) } == 0 {
+                    return Ok(());
+                }
+
+                match unsafe { *libc::__errno_location() } {
+                    // already renamed by a previous (interrupted) boot
+                    libc::ENOENT if crate::check_file_exists(to.as_str())? => Ok(()),
+                    err => Err(err),
+                }
+            }
+            Self::DeleteSubvolume(path) => match crate::btrfs::delete_subvolume(path) {
+                Err(libc::ENOENT) => Ok(()),
+                result => result,
+            },
+            Self::Truncate(path) => remove_directory_contents(path, true),
+        }
+    }
+}
+
+/// Persist the given status byte for the line starting at offset.
+fn mark(fd: libc::c_int, offset: usize, status: u8) -> Result<(), libc::c_int> {
+    unsafe {
+        if libc::pwrite(
+            fd,
+            &status as *const u8 as *const libc::c_void,
+            1,
+            offset as libc::off_t,
+        ) != 1
+        {
+            return Err(*libc::__errno_location());
+        }
+
+        if libc::fsync(fd) != 0 {
+            return Err(*libc::__errno_location());
+        }
+    }
+
+    Ok(())
+}
+
+fn replay_content(fd: libc::c_int, content: &[u8]) -> Result<(), libc::c_int> {
+    let mut offset = 0;
+
+    for line in content.split(|&ch| ch == b'\n') {
+        let line_offset = offset;
+        offset += line.len() + 1;
+
+        match line.first() {
+            Some(&PENDING) => {}
+            // already performed, a comment or an empty line
+            _ => continue,
+        }
+
+        let parsed = core::str::from_utf8(&line[1..])
+            .map_err(|_| libc::EINVAL)
+            .and_then(Operation::parse);
+
+        // a malformed line would block every later operation forever: set it aside
+        let operation = match parsed {
+            Ok(operation) => operation,
+            Err(err) => {
+                unsafe {
+                    libc::printf(
+                        b"Skipping invalid deferred operation at byte %llu: %d\n\0".as_ptr()
+                            as *const libc::c_char,
+                        line_offset as libc::c_ulonglong,
+                        err as libc::c_int,
+                    );
+                }
+
+                mark(fd, line_offset, INVALID)?;
+                continue;
+            }
+        };
+
+        #[cfg(feature = "trace")]
+        unsafe {
+            libc::printf(
+                b"Performing deferred operation at byte %llu\n\0".as_ptr() as *const libc::c_char,
+                line_offset as libc::c_ulonglong,
+            );
+        }
+
+        // operations are performed strictly in order: stop at the first failure
+        operation.execute()?;
+
+        mark(fd, line_offset, DONE)?;
+    }
+
+    Ok(())
+}
+
+/**
+ * Replay every pending operation listed in the journal at the given path.
+ *
+ * Each line of the journal is either empty, a # comment, or an operation
+ * prefixed by a status byte: '-' for pending, '+' for done and '!' for
+ * a line that could not be parsed, which is never attempted again.
+ * Once an operation succeeds its status byte is flipped to '+' and the
+ * journal is fsync'ed, so that a power loss at any point never repeats
+ * a completed step and never skips a pending one.
+ *
+ * A missing journal means there is nothing to do, while a journal larger
+ * than max_file_size is rejected with EFBIG rather than partially replayed.
+ */
+pub fn replay(path: &str, max_file_size: usize) -> Result<(), libc::c_int> {
+    let path_str = CStr::new(path)?;
+
+    let fd = unsafe { libc::open(path_str.inner(), libc::O_RDWR | libc::O_CLOEXEC) };
+    if fd < 0 {
+        return match unsafe { *libc::__errno_location() } {
+            libc::ENOENT => Ok(()),
+            err => Err(err),
+        };
+    }
+
+    let result = match crate::read_complete_fd(fd, max_file_size) {
+        Ok(content) => match content.as_slice() {
+            Some(content) => replay_content(fd, content),
+            None => Ok(()),
+        },
+        Err(err) => Err(err),
+    };
+
+    unsafe { libc::close(fd) };
+
+    result
+}
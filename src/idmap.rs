@@ -0,0 +1,236 @@
+/// Maximum number of ranges in a uid or gid map.
+pub const IDMAP_MAX_RANGES: usize = 5;
+
+/// Size of the buffer holding a map in the uid_map/gid_map format.
+const IDMAP_TEXT_MAX: usize = 256;
+
+/// Maps count ids starting at inside (in the namespace) to the ones starting at outside.
+#[derive(Copy, Clone, Debug, PartialEq, Default)]
+pub struct IdRange {
+    pub inside: u32,
+    pub outside: u32,
+    pub count: u32,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Default)]
+struct Ranges {
+    ranges: [IdRange; IDMAP_MAX_RANGES],
+    len: usize,
+}
+
+impl Ranges {
+    /// Parse ranges given as <inside>-<outside>-<count>, separated by '/'.
+    fn parse(value: &str) -> Result<Self, libc::c_int> {
+        let mut ranges = Self::default();
+
+        for range in value.split('/') {
+            let mut fields = range.split('-').map(|field| field.parse::<u32>());
+
+            let (Some(Ok(inside)), Some(Ok(outside)), Some(Ok(count)), None) =
+                (fields.next(), fields.next(), fields.next(), fields.next())
+            else {
+                return Err(libc::EINVAL);
+            };
+
+            if count == 0 || ranges.len == IDMAP_MAX_RANGES {
+                return Err(libc::EINVAL);
+            }
+
+            ranges.ranges[ranges.len] = IdRange {
+                inside,
+                outside,
+                count,
+            };
+            ranges.len += 1;
+        }
+
+        Ok(ranges)
+    }
+
+    fn as_slice(&self) -> &[IdRange] {
+        &self.ranges[..self.len]
+    }
+
+    /**
+     * Write the ranges to /proc/<pid>/uid_map or /proc/<pid>/gid_map: the kernel
+     * requires the whole map to be given in a single write.
+     */
+    fn write(&self, pid: libc::pid_t, file: &[u8]) -> Result<(), libc::c_int> {
+        let mut path = [0u8; 64];
+        let mut text = [0u8; IDMAP_TEXT_MAX];
+        let mut len = 0;
+
+        for range in self.as_slice() {
+            let written = unsafe {
+                libc::snprintf(
+                    text[len..].as_mut_ptr() as *mut libc::c_char,
+                    IDMAP_TEXT_MAX - len,
+                    b"%u %u %u\n\0".as_ptr() as *const libc::c_char,
+                    range.inside as libc::c_uint,
+                    range.outside as libc::c_uint,
+                    range.count as libc::c_uint,
+                )
+            };
+
+            len += written as usize;
+        }
+
+        unsafe {
+            libc::snprintf(
+                path.as_mut_ptr() as *mut libc::c_char,
+                path.len(),
+                b"/proc/%d/%s\0".as_ptr() as *const libc::c_char,
+                pid,
+                file.as_ptr() as *const libc::c_char,
+            );
+
+            let fd = libc::open(
+                path.as_ptr() as *const libc::c_char,
+                libc::O_WRONLY | libc::O_CLOEXEC,
+            );
+            if fd < 0 {
+                return Err(*libc::__errno_location());
+            }
+
+            let written = libc::write(fd, text.as_ptr() as *const libc::c_void, len);
+            let err = *libc::__errno_location();
+            libc::close(fd);
+
+            if written != len as isize {
+                return Err(err);
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// The uid and gid mappings of an ID-mapped mount.
+#[derive(Copy, Clone, Debug, PartialEq, Default)]
+pub struct IdMap {
+    uid: Ranges,
+    gid: Ranges,
+}
+
+impl IdMap {
+    /**
+     * Parse a mapping given as <uid-map>:<gid-map>, each map being one or more
+     * <inside>-<outside>-<count> ranges separated by '/', e.g. 0-100000-65536:0-100000-65536.
+     */
+    pub fn parse(value: &str) -> Result<Self, libc::c_int> {
+        let (uid, gid) = value.split_once(':').ok_or(libc::EINVAL)?;
+
+        Ok(Self {
+            uid: Ranges::parse(uid)?,
+            gid: Ranges::parse(gid)?,
+        })
+    }
+
+    /**
+     * Create a user namespace with this mapping, as needed by MOUNT_ATTR_IDMAP.
+     *
+     * A child process unshares the namespace and waits until its maps are written
+     * and the namespace is opened, then it is killed: the namespace lives on as
+     * long as the returned file descriptor is open.
+     *
+     * @return a file descriptor referring to the user namespace
+     */
+    pub fn user_namespace(&self) -> Result<libc::c_int, libc::c_int> {
+        let mut pipe_fds = [0 as libc::c_int; 2];
+
+        if unsafe { libc::pipe2(pipe_fds.as_mut_ptr(), libc::O_CLOEXEC) } != 0 {
+            return Err(unsafe { *libc::__errno_location() });
+        }
+
+        let pid = unsafe { libc::fork() };
+
+        if pid < 0 {
+            let err = unsafe { *libc::__errno_location() };
+            unsafe {
+                libc::close(pipe_fds[0]);
+                libc::close(pipe_fds[1]);
+            }
+            return Err(err);
+        }
+
+        if pid == 0 {
+            unsafe {
+                // report the outcome of unshare(2) to the parent, then wait to be killed
+                let err = match libc::unshare(libc::CLONE_NEWUSER) {
+                    0 => 0,
+                    _ => *libc::__errno_location(),
+                };
+
+                libc::write(
+                    pipe_fds[1],
+                    &err as *const libc::c_int as *const libc::c_void,
+                    core::mem::size_of::<libc::c_int>(),
+                );
+
+                loop {
+                    libc::pause();
+                }
+            }
+        }
+
+        unsafe { libc::close(pipe_fds[1]) };
+
+        let result = self.open_user_namespace(pid, pipe_fds[0]);
+
+        unsafe {
+            libc::close(pipe_fds[0]);
+            libc::kill(pid, libc::SIGKILL);
+            libc::waitpid(pid, core::ptr::null_mut(), 0);
+        }
+
+        result
+    }
+
+    fn open_user_namespace(
+        &self,
+        pid: libc::pid_t,
+        pipe_fd: libc::c_int,
+    ) -> Result<libc::c_int, libc::c_int> {
+        let mut err: libc::c_int = 0;
+
+        let bytes_read = unsafe {
+            libc::read(
+                pipe_fd,
+                &mut err as *mut libc::c_int as *mut libc::c_void,
+                core::mem::size_of::<libc::c_int>(),
+            )
+        };
+
+        if bytes_read != core::mem::size_of::<libc::c_int>() as isize {
+            return Err(libc::ECHILD);
+        }
+
+        if err != 0 {
+            return Err(err);
+        }
+
+        self.uid.write(pid, b"uid_map\0")?;
+        self.gid.write(pid, b"gid_map\0")?;
+
+        let mut path = [0u8; 64];
+
+        unsafe {
+            libc::snprintf(
+                path.as_mut_ptr() as *mut libc::c_char,
+                path.len(),
+                b"/proc/%d/ns/user\0".as_ptr() as *const libc::c_char,
+                pid,
+            );
+
+            let fd = libc::open(
+                path.as_ptr() as *const libc::c_char,
+                libc::O_RDONLY | libc::O_CLOEXEC,
+            );
+            if fd < 0 {
+                return Err(*libc::__errno_location());
+            }
+
+            Ok(fd)
+        }
+    }
+}
@@ -0,0 +1,266 @@
+use crate::string::CStr;
+use crate::vector::Vec;
+
+/// Size of the start of a program inspected for a #! line or an ELF header, as the kernel does.
+const PROGRAM_HEADER_SIZE: usize = 256;
+
+/// Maximum size of an ELF program header read.
+const ELF_PROGRAM_HEADER_MAX: usize = 64;
+
+/// Maximum number of interpreters followed (e.g. a script run by a script).
+const INTERPRETER_MAX_DEPTH: usize = 4;
+
+/**
+ * Open the given path as if root were /: symlinks (even absolute ones) and .. never
+ * escape it. Kernels older than 5.6 lack openat2(2): the path is then simply
+ * resolved below root.
+ */
+fn open_in_root(root_fd: libc::c_int, path: &str) -> Result<libc::c_int, libc::c_int> {
+    let mut how: libc::open_how = unsafe { core::mem::zeroed() };
+    how.flags = (libc::O_RDONLY | libc::O_CLOEXEC) as u64;
+    how.resolve = libc::RESOLVE_IN_ROOT;
+
+    let path_str = CStr::new(path)?;
+
+    let fd = unsafe {
+        /*
+         * On success, a new file descriptor is returned. On error, -1 is returned,
+         * and errno is set to indicate the error.
+         */
+        libc::syscall(
+            libc::SYS_openat2,
+            root_fd,
+            path_str.inner(),
+            &how as *const libc::open_how,
+            core::mem::size_of::<libc::open_how>(),
+        ) as libc::c_int
+    };
+
+    if fd >= 0 {
+        return Ok(fd);
+    }
+
+    match unsafe { *libc::__errno_location() } {
+        libc::ENOSYS => {}
+        err => return Err(err),
+    }
+
+    let relative_path = CStr::new(path.trim_start_matches('/'))?;
+
+    let fd = unsafe {
+        libc::openat(
+            root_fd,
+            relative_path.inner(),
+            libc::O_RDONLY | libc::O_CLOEXEC,
+        )
+    };
+
+    match fd < 0 {
+        true => Err(unsafe { *libc::__errno_location() }),
+        false => Ok(fd),
+    }
+}
+
+fn read_at(fd: libc::c_int, buf: &mut [u8], offset: u64) -> Result<usize, libc::c_int> {
+    // off_t is 32 bits wide on some targets
+    let offset = libc::off_t::try_from(offset).map_err(|_| libc::EOVERFLOW)?;
+
+    let bytes_read =
+        unsafe { libc::pread(fd, buf.as_mut_ptr() as *mut libc::c_void, buf.len(), offset) };
+
+    if bytes_read < 0 {
+        return Err(unsafe { *libc::__errno_location() });
+    }
+
+    Ok(bytes_read as usize)
+}
+
+/// Read an unsigned integer of the given size (in bytes) and endianness.
+fn read_uint(buf: &[u8], offset: usize, size: usize, big_endian: bool) -> Option<u64> {
+    let bytes = buf.get(offset..offset + size)?;
+
+    Some(match big_endian {
+        true => bytes
+            .iter()
+            .fold(0, |value, byte| (value << 8) | *byte as u64),
+        false => bytes
+            .iter()
+            .rev()
+            .fold(0, |value, byte| (value << 8) | *byte as u64),
+    })
+}
+
+/**
+ * Find the program interpreter (the dynamic loader, e.g. /lib/ld-linux-aarch64.so.1)
+ * requested by the PT_INTERP program header of an ELF executable.
+ *
+ * @param header the start of the executable
+ * @return the path of the interpreter, None for a static executable
+ */
+fn elf_interpreter(fd: libc::c_int, header: &[u8]) -> Result<Option<Vec<u8>>, libc::c_int> {
+    let class64 = header.get(libc::EI_CLASS) == Some(&libc::ELFCLASS64);
+    let big_endian = header.get(libc::EI_DATA) == Some(&libc::ELFDATA2MSB);
+    let uint = |buf: &[u8], offset, size| read_uint(buf, offset, size, big_endian);
+
+    let (phoff, phentsize, phnum) = match class64 {
+        true => (
+            uint(header, 0x20, 8),
+            uint(header, 0x36, 2),
+            uint(header, 0x38, 2),
+        ),
+        false => (
+            uint(header, 0x1c, 4),
+            uint(header, 0x2a, 2),
+            uint(header, 0x2c, 2),
+        ),
+    };
+
+    let (Some(phoff), Some(phentsize), Some(phnum)) = (phoff, phentsize, phnum) else {
+        return Err(libc::ENOEXEC);
+    };
+
+    for index in 0..phnum {
+        let mut program_header = [0u8; ELF_PROGRAM_HEADER_MAX];
+        let len = (phentsize as usize).min(ELF_PROGRAM_HEADER_MAX);
+
+        // the offsets come from the file: an overflow means it is corrupt
+        let Some(header_offset) = index
+            .checked_mul(phentsize)
+            .and_then(|offset| offset.checked_add(phoff))
+        else {
+            return Err(libc::ENOEXEC);
+        };
+
+        let len = read_at(fd, &mut program_header[..len], header_offset)?;
+        let program_header = &program_header[..len];
+
+        if uint(program_header, 0, 4) != Some(libc::PT_INTERP as u64) {
+            continue;
+        }
+
+        let (offset, size) = match class64 {
+            true => (uint(program_header, 0x08, 8), uint(program_header, 0x20, 8)),
+            false => (uint(program_header, 0x04, 4), uint(program_header, 0x10, 4)),
+        };
+
+        let (Some(offset), Some(size)) = (offset, size) else {
+            return Err(libc::ENOEXEC);
+        };
+
+        if size == 0 || size as usize > libc::PATH_MAX as usize {
+            return Err(libc::ENOEXEC);
+        }
+
+        let mut interpreter = Vec::<u8>::with_capacity(size as usize)?;
+        interpreter.fill_by_function(|ptr, capacity| {
+            read_at(
+                fd,
+                unsafe { core::slice::from_raw_parts_mut(ptr, capacity) },
+                offset,
+            )
+        })?;
+
+        // the path is NUL-terminated
+        while interpreter
+            .as_slice()
+            .is_some_and(|path| path.ends_with(&[0]))
+        {
+            interpreter.pop();
+        }
+
+        return Ok(Some(interpreter));
+    }
+
+    Ok(None)
+}
+
+fn check_program_in_root(
+    root_fd: libc::c_int,
+    path: &str,
+    depth: usize,
+) -> Result<(), libc::c_int> {
+    if depth > INTERPRETER_MAX_DEPTH {
+        return Err(libc::ELOOP);
+    }
+
+    let fd = open_in_root(root_fd, path)?;
+    let result = check_program_fd(root_fd, fd, depth);
+
+    unsafe { libc::close(fd) };
+
+    result
+}
+
+fn check_program_fd(
+    root_fd: libc::c_int,
+    fd: libc::c_int,
+    depth: usize,
+) -> Result<(), libc::c_int> {
+    let mut stat_buf: libc::stat = unsafe { core::mem::zeroed() };
+    if unsafe { libc::fstat(fd, &mut stat_buf) } != 0 {
+        return Err(unsafe { *libc::__errno_location() });
+    }
+
+    if (stat_buf.st_mode & libc::S_IFMT) != libc::S_IFREG || (stat_buf.st_mode & 0o111) == 0 {
+        return Err(libc::EACCES);
+    }
+
+    let mut header = [0u8; PROGRAM_HEADER_SIZE];
+    let len = read_at(fd, &mut header, 0)?;
+    let header = &header[..len];
+
+    // a script: #!/bin/sh -e
+    if let Some(line) = header.strip_prefix(b"#!") {
+        let line = line.split(|byte| *byte == b'\n').next().unwrap_or(&[]);
+        let interpreter = core::str::from_utf8(line)
+            .map_err(|_| libc::ENOEXEC)?
+            .split_ascii_whitespace()
+            .next()
+            .ok_or(libc::ENOEXEC)?;
+
+        return check_program_in_root(root_fd, interpreter, depth + 1);
+    }
+
+    if header.starts_with(&[libc::ELFMAG0, libc::ELFMAG1, libc::ELFMAG2, libc::ELFMAG3]) {
+        if let Some(interpreter) = elf_interpreter(fd, header)? {
+            let interpreter = core::str::from_utf8(interpreter.as_slice().unwrap_or(&[]))
+                .map_err(|_| libc::ENOEXEC)?;
+
+            return check_program_in_root(root_fd, interpreter, depth + 1);
+        }
+    }
+
+    Ok(())
+}
+
+/**
+ * Check that the given program can be executed once root becomes /: it must be an
+ * executable regular file and the interpreter it requests, either the dynamic loader
+ * of an ELF executable or the #! line of a script, must be so too.
+ *
+ * Paths are resolved as if root were /, so that absolute symlinks are followed
+ * within it.
+ *
+ * @param root where the new root is mounted
+ * @param program the path of the program within the new root
+ */
+pub fn check_program(root: &str, program: &str) -> Result<(), libc::c_int> {
+    let root_str = CStr::new(root)?;
+
+    let root_fd = unsafe {
+        libc::open(
+            root_str.inner(),
+            libc::O_PATH | libc::O_DIRECTORY | libc::O_CLOEXEC,
+        )
+    };
+
+    if root_fd < 0 {
+        return Err(unsafe { *libc::__errno_location() });
+    }
+
+    let result = check_program_in_root(root_fd, program, 0);
+
+    unsafe { libc::close(root_fd) };
+
+    result
+}
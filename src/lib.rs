@@ -3,10 +3,19 @@
 
 extern crate libc;
 
+pub mod btrfs;
 pub mod change_dir;
+pub mod command;
 pub mod config;
+pub mod deferred;
+pub mod idmap;
+pub mod init;
 pub mod link;
+pub mod mkdir;
 pub mod mount;
+pub mod remove;
+pub mod reset;
+pub mod snapshot;
 pub mod string;
 pub mod switch_root;
 pub mod vector;
@@ -15,16 +24,121 @@ pub const VERSION: &str = env!("CARGO_PKG_VERSION");
 
 pub const SYSROOT: &str = "/mnt";
 
+#[cfg(target_arch = "x86")]
+pub const ARCH: &str = "x86";
+
+#[cfg(target_arch = "x86_64")]
+pub const ARCH: &str = "x86_64";
+
+#[cfg(target_arch = "arm")]
+pub const ARCH: &str = "arm";
+
+#[cfg(target_arch = "aarch64")]
+pub const ARCH: &str = "aarch64";
+
+#[cfg(target_arch = "riscv32")]
+pub const ARCH: &str = "riscv32";
+
+#[cfg(target_arch = "riscv64")]
+pub const ARCH: &str = "riscv64";
+
+#[cfg(target_arch = "powerpc")]
+pub const ARCH: &str = "powerpc";
+
+#[cfg(target_arch = "powerpc64")]
+pub const ARCH: &str = "powerpc64";
+
+#[cfg(target_arch = "s390x")]
+pub const ARCH: &str = "s390x";
+
+#[cfg(target_arch = "mips")]
+pub const ARCH: &str = "mips";
+
+#[cfg(target_arch = "mips64")]
+pub const ARCH: &str = "mips64";
+
+#[cfg(target_arch = "loongarch64")]
+pub const ARCH: &str = "loongarch64";
+
+#[cfg(target_arch = "sparc64")]
+pub const ARCH: &str = "sparc64";
+
+#[cfg(not(any(
+    target_arch = "x86",
+    target_arch = "x86_64",
+    target_arch = "arm",
+    target_arch = "aarch64",
+    target_arch = "riscv32",
+    target_arch = "riscv64",
+    target_arch = "powerpc",
+    target_arch = "powerpc64",
+    target_arch = "s390x",
+    target_arch = "mips",
+    target_arch = "mips64",
+    target_arch = "loongarch64",
+    target_arch = "sparc64"
+)))]
+pub const ARCH: &str = "unknown";
+
 pub const DEFAULT_INIT: &str = "/sbin/init";
+/// Init programs tried, in order, when none of /etc/rdexec and init= can be executed.
+pub const FALLBACK_INITS: &[&str] = &[DEFAULT_INIT, "/lib/systemd/systemd", "/bin/sh"];
+pub const INIT_DEFAULT_PATH: &str =
+    "PATH=/usr/local/sbin:/usr/local/bin:/usr/sbin:/usr/bin:/sbin:/bin";
+pub const INIT_DEFAULT_TERM: &str = "TERM=linux";
+
+pub const SELF_CMDLINE_PATH: &str = "/proc/self/cmdline";
+pub const SELF_CMDLINE_MAX_FILE_SIZE: usize = 4096;
+pub const SELF_ENVIRON_PATH: &str = "/proc/self/environ";
+pub const SELF_ENVIRON_MAX_FILE_SIZE: usize = 4096;
+
+/// Mounts of the early root moved into the new root before switching, unless rd.move-mounts= is given.
+pub const DEFAULT_MOVE_MOUNTS: &str = "/dev,/proc,/sys,/run";
+
+pub const WAIT_POLL_INTERVAL_US: libc::c_uint = 100_000;
+
+pub const DEPLOYMENTS_PATH: &str = "/deployments";
+pub const SNAPSHOTS_DIR: &str = ".snapshots";
+pub const DEFAULT_SNAPSHOTS_KEEP: usize = 3;
+
+pub const RDFIRSTBOOT_PATH: &str = "/etc/rdfirstboot";
+pub const RDFIRSTBOOT_MAX_FILE_SIZE: usize = 256;
+
+pub const RDOPS_PATH: &str = "/etc/rdops";
+pub const RDOPS_MAX_FILE_SIZE: usize = 16384;
 
 pub const RDNAME_PATH: &str = "/etc/rdname";
 pub const RDNAME_MAX_FILE_SIZE: usize = 256;
 
 pub const RDEXEC_PATH: &str = "/etc/rdexec";
-pub const RDEXEC_MAX_FILE_SIZE: usize = 256;
+pub const RDEXEC_MAX_FILE_SIZE: usize = 4096;
+
+pub const RDRESET_PATH: &str = "/etc/rdreset";
 
 pub const RDTAB_PATH: &str = "/mnt/etc/rdtab";
 pub const RDTAB_MAX_FILE_SIZE: usize = 16384;
+pub const RDTAB_DROPIN_PATH: &str = "/mnt/etc/rdtab.d";
+pub const RDTAB_DROPIN_SUFFIX: &str = ".rdtab";
+
+pub const MOUNTINFO_PATH: &str = "/proc/self/mountinfo";
+pub const MOUNTINFO_MAX_FILE_SIZE: usize = 65536;
+
+/**
+ * Clean up the path of a deployment (i.e. /deployments/<name>) from surrounding
+ * whitespaces and terminators, returning it together with the deployment name.
+ */
+pub fn split_deployment_path(path: &str) -> Option<(&str, &str)> {
+    let path = path
+        .trim_matches(|ch: char| ch.is_whitespace() || ch == '\0')
+        .trim_end_matches('/');
+
+    let name = &path[path.rfind('/')? + 1..];
+
+    match name.is_empty() {
+        true => None,
+        false => Some((path, name)),
+    }
+}
 
 pub fn check_file_exists(path: &str) -> Result<bool, libc::c_int> {
     let path_str = crate::string::CStr::new(path)?;
@@ -32,6 +146,78 @@ pub fn check_file_exists(path: &str) -> Result<bool, libc::c_int> {
     Ok(unsafe { libc::access(path_str.inner(), 0) } == libc::F_OK)
 }
 
+/**
+ * Wait for the given path to appear, checking every WAIT_POLL_INTERVAL_US
+ * for up to timeout seconds.
+ *
+ * @return whether the path exists
+ */
+pub fn wait_for_path(path: &str, timeout: libc::c_uint) -> bool {
+    let attempts = (timeout as u64 * 1_000_000) / WAIT_POLL_INTERVAL_US as u64;
+
+    for _ in 0..attempts {
+        if check_file_exists(path).unwrap_or(false) {
+            return true;
+        }
+
+        unsafe { libc::usleep(WAIT_POLL_INTERVAL_US) };
+    }
+
+    check_file_exists(path).unwrap_or(false)
+}
+
+/// Read the file open as fd up to its end, failing with EFBIG rather than truncating it.
+pub fn read_complete_fd(
+    fd: libc::c_int,
+    max_file_size: usize,
+) -> Result<crate::vector::Vec<u8>, libc::c_int> {
+    // one more byte than allowed tells a file of exactly max_file_size from a larger one
+    let mut content = crate::vector::Vec::<u8>::with_capacity(max_file_size + 1)?;
+
+    content.fill_by_function(|ptr, capacity| {
+        let mut total = 0;
+
+        while total < capacity {
+            let bytes_read =
+                unsafe { libc::read(fd, ptr.add(total) as *mut libc::c_void, capacity - total) };
+
+            match bytes_read {
+                0 => break,
+                bytes_read if bytes_read < 0 => match unsafe { *libc::__errno_location() } {
+                    libc::EINTR => continue,
+                    err => return Err(err),
+                },
+                bytes_read => total += bytes_read as usize,
+            }
+        }
+
+        Ok(total)
+    })?;
+
+    match content.len() > max_file_size {
+        true => Err(libc::EFBIG),
+        false => Ok(content),
+    }
+}
+
+/// Read the whole file like read_whole_file, failing with EFBIG rather than truncating it.
+pub fn read_complete_file(
+    path: &str,
+    max_file_size: usize,
+) -> Result<crate::vector::Vec<u8>, libc::c_int> {
+    let path_str = crate::string::CStr::new(path)?;
+
+    let fd = unsafe { libc::open(path_str.inner(), libc::O_RDONLY | libc::O_CLOEXEC) };
+    if fd < 0 {
+        return Err(unsafe { *libc::__errno_location() });
+    }
+
+    let content = read_complete_fd(fd, max_file_size);
+    unsafe { libc::close(fd) };
+
+    content
+}
+
 pub fn read_whole_file(
     path: &str,
     max_file_size: usize,
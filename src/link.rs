@@ -16,3 +16,34 @@ pub fn create_hardlink(src: &str, dst: &str) -> Result<(), libc::c_int> {
 
     Ok(())
 }
+
+pub fn create_symlink(target: &str, linkpath: &str) -> Result<(), libc::c_int> {
+    let target = CStr::new(target)?;
+    let linkpath = CStr::new(linkpath)?;
+
+    unsafe {
+        /*
+         * On success, zero is returned. On error, -1 is returned, and errno
+         * is set to indicate the error.
+         */
+        if libc::symlink(target.inner(), linkpath.inner()) != 0 {
+            return Err(*libc::__errno_location());
+        }
+    }
+
+    Ok(())
+}
+
+/// Check whether the given path is a symbolic link (without following it).
+pub fn is_symlink(path: &str) -> bool {
+    let Ok(path) = CStr::new(path) else {
+        return false;
+    };
+
+    let mut stat_buf: libc::stat = unsafe { core::mem::zeroed() };
+    if unsafe { libc::lstat(path.inner(), &mut stat_buf) } != 0 {
+        return false;
+    }
+
+    (stat_buf.st_mode & libc::S_IFMT) == libc::S_IFLNK
+}
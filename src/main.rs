@@ -3,10 +3,13 @@
 extern crate libc;
 
 use atomrootfsinit::{
-    config::Config,
-    mount::{MountFlag, Mountpoint, MountpointFlags},
+    command::Command,
+    config::{Config, Context, Entry},
+    init::check_program,
+    mount::{direct_detach, MountFlag, Mountpoint, MountpointFlags},
     string::CStr,
-    switch_root::switch_root,
+    switch_root::{move_into_new_root, new_root_path, root_is_initramfs, switch_root},
+    vector::Vec,
 };
 
 // Macro to print DEBUG messages only in debug builds
@@ -29,8 +32,13 @@ macro_rules! debug_printf {
 }
 
 pub(crate) struct CmdLine {
+    content: CStr,
     root: Option<CStr>,
     init: Option<CStr>,
+    factory_reset: bool,
+    keep_initramfs: bool,
+    move_mounts: Option<CStr>,
+    initramfs: Option<bool>,
 }
 
 fn read_partuuid_from_sys(
@@ -998,12 +1006,34 @@ fn find_device_by_partuuid(
     result
 }
 
+/// Retry the PARTUUID lookup once per second, as the device may be slow to show up.
+fn find_device_by_partuuid_with_timeout(
+    needle: &str,
+    sys_mount: &str,
+    dev_mount: &str,
+    timeout: libc::c_uint,
+) -> Option<atomrootfsinit::vector::Vec<u8>> {
+    for _ in 0..timeout {
+        if let Some(device) = find_device_by_partuuid(needle, sys_mount, dev_mount) {
+            return Some(device);
+        }
+
+        unsafe { libc::sleep(1) };
+    }
+
+    find_device_by_partuuid(needle, sys_mount, dev_mount)
+}
+
 fn read_cmdline() -> Option<CmdLine> {
     match atomrootfsinit::read_whole_file("/proc/cmdline", atomrootfsinit::RDTAB_MAX_FILE_SIZE) {
         Ok(cmdline) => match core::str::from_utf8(cmdline.as_slice().unwrap()) {
             Ok(cmdline_str) => {
                 let mut root = None;
                 let mut init = None;
+                let mut factory_reset = false;
+                let mut keep_initramfs = false;
+                let mut move_mounts = None;
+                let mut initramfs = None;
                 for param in cmdline_str.split_ascii_whitespace() {
                     if param.starts_with("root=") {
                         root = Some(CStr::new(&param[5..param.len()]).unwrap_or_else(
@@ -1029,10 +1059,51 @@ fn read_cmdline() -> Option<CmdLine> {
                                 libc::exit(err);
                             },
                         ));
+                    } else if param == "rd.factory-reset" {
+                        factory_reset = true;
+                    } else if param == "rd.keep-initramfs" {
+                        keep_initramfs = true;
+                    } else if param == "rd.initramfs=1" {
+                        initramfs = Some(true);
+                    } else if param == "rd.initramfs=0" {
+                        initramfs = Some(false);
+                    } else if param.starts_with("rd.move-mounts=") {
+                        move_mounts = Some(
+                            CStr::new(&param["rd.move-mounts=".len()..]).unwrap_or_else(
+                                |err| unsafe {
+                                    libc::printf(
+                                        b"Failed to store the mounts to be moved: %d\n\0"
+                                            .as_ptr()
+                                            as *const libc::c_char,
+                                        err as libc::c_int,
+                                    );
+                                    libc::sleep(10);
+                                    libc::exit(err);
+                                },
+                            ),
+                        );
                     }
                 }
 
-                Some(CmdLine { root, init })
+                let content = CStr::new(cmdline_str.trim()).unwrap_or_else(|err| unsafe {
+                    libc::printf(
+                        b"Failed to store the kernel cmdline: %d\n\0".as_ptr()
+                            as *const libc::c_char,
+                        err as libc::c_int,
+                    );
+                    libc::sleep(10);
+                    libc::exit(err);
+                });
+
+                Some(CmdLine {
+                    content,
+                    root,
+                    init,
+                    factory_reset,
+                    keep_initramfs,
+                    move_mounts,
+                    initramfs,
+                })
             }
             Err(_err) => unsafe {
                 libc::printf(
@@ -1074,7 +1145,7 @@ fn main() {
         None,
         SLASH,
         None,
-        MountpointFlags::new(&[MountFlag::Recursive, MountFlag::Private]),
+        MountpointFlags::new(&[MountFlag::RecursivePropagation, MountFlag::Private]),
         None,
     )
     .unwrap_or_else(|err| {
@@ -1092,7 +1163,7 @@ fn main() {
     .unwrap_or_else(|err| unsafe {
         libc::printf(
             b"Failed to remount / as private: %d\n\0".as_ptr() as *const libc::c_char,
-            err as libc::c_int,
+            err.errno(),
         );
     });
 
@@ -1109,6 +1180,21 @@ fn main() {
         );
     }
 
+    // Deployments are not in use yet: perform destructive operations deferred by the updater
+    if let Err(err) = atomrootfsinit::deferred::replay(
+        atomrootfsinit::RDOPS_PATH,
+        atomrootfsinit::RDOPS_MAX_FILE_SIZE,
+    ) {
+        unsafe {
+            libc::printf(
+                b"Failed to replay deferred operations: %d\n\0".as_ptr() as *const libc::c_char,
+                err as libc::c_int,
+            );
+        }
+    }
+
+    let mut rdname: Option<CStr> = None;
+
     (match atomrootfsinit::read_whole_file(
         atomrootfsinit::RDNAME_PATH,
         atomrootfsinit::RDNAME_MAX_FILE_SIZE,
@@ -1180,16 +1266,49 @@ fn main() {
                     MountpointFlags::new(&[MountFlag::Bind]),
                     None,
                 ),
-                false => Mountpoint::new(
-                    Some(
-                        core::str::from_utf8(rdname_content.as_slice().unwrap_or(&[]))
-                            .unwrap_or(""),
-                    ),
-                    "/mnt",
-                    Some("bind"),
-                    MountpointFlags::new(&[MountFlag::Bind]),
-                    None,
-                ),
+                false => {
+                    let deployment = core::str::from_utf8(rdname_content.as_slice().unwrap_or(&[]))
+                        .unwrap_or("");
+
+                    rdname = atomrootfsinit::split_deployment_path(deployment)
+                        .and_then(|(_, name)| CStr::new(name).ok());
+
+                    // preserve the state of the deployment before the updated system first runs
+                    if atomrootfsinit::snapshot::snapshot_requested() {
+                        match atomrootfsinit::snapshot::snapshot_deployment(
+                            deployment,
+                            atomrootfsinit::snapshot::snapshots_to_keep(),
+                        ) {
+                            Ok(()) => {
+                                if let Err(err) = atomrootfsinit::snapshot::consume_trigger() {
+                                    unsafe {
+                                        libc::printf(
+                                            b"Failed to remove the first boot marker: %d\n\0"
+                                                .as_ptr()
+                                                as *const libc::c_char,
+                                            err as libc::c_int,
+                                        );
+                                    }
+                                }
+                            }
+                            Err(err) => unsafe {
+                                libc::printf(
+                                    b"Failed to snapshot the deployment: %d\n\0".as_ptr()
+                                        as *const libc::c_char,
+                                    err as libc::c_int,
+                                );
+                            },
+                        }
+                    }
+
+                    Mountpoint::new(
+                        Some(deployment),
+                        "/mnt",
+                        Some("bind"),
+                        MountpointFlags::new(&[MountFlag::Bind]),
+                        None,
+                    )
+                }
             }
         }
         Err(err) => {
@@ -1226,39 +1345,14 @@ fn main() {
         unsafe {
             libc::printf(
                 b"Failed to mount /mnt: %d\n\0".as_ptr() as *const libc::c_char,
-                err as libc::c_int,
+                err.errno(),
             );
         }
-        exit_error(err);
+        exit_error(err.errno());
 
         unreachable!()
     });
 
-    let config = match atomrootfsinit::read_whole_file(
-        atomrootfsinit::RDTAB_PATH,
-        atomrootfsinit::RDTAB_MAX_FILE_SIZE,
-    ) {
-        Ok(rdinit_content) => Config::new(rdinit_content).unwrap_or_else(|err| {
-            unsafe {
-                libc::printf(
-                    b"Failed to parse configuration: %d\n\0".as_ptr() as *const libc::c_char,
-                    err as libc::c_int,
-                );
-            }
-            exit_error(err);
-            unreachable!()
-        }),
-        Err(err) => {
-            unsafe {
-                libc::printf(
-                    b"Failed to read configuration file: %d\n\0".as_ptr() as *const libc::c_char,
-                    err as libc::c_int,
-                );
-            }
-            return exit_error(err);
-        }
-    };
-
     // mount proc into /proc as rw so that /proc/cmdline and /proc/mounts will be accessible
     Mountpoint::new(
         Some("proc"),
@@ -1283,106 +1377,125 @@ fn main() {
     .unwrap_or_else(|err| unsafe {
         libc::printf(
             b"Failed to mount /proc as private: %d\n\0".as_ptr() as *const libc::c_char,
-            err as libc::c_int,
+            err.errno(),
         );
     });
 
-    let initramfs =
-        atomrootfsinit::read_whole_file("/proc/mounts", atomrootfsinit::RDEXEC_MAX_FILE_SIZE)
-            .unwrap_or_else(|err| {
-                unsafe {
-                    libc::printf(
-                        b"Failed to open /proc/mounts: %d\n\0".as_ptr() as *const libc::c_char,
-                        err as libc::c_int,
-                    );
-                }
+    let cmdline = read_cmdline();
 
-                exit_error(err);
+    // MS_MOVE and chroot on the initramfs, where pivot_root is not possible, pivot_root otherwise
+    let initramfs = match cmdline.as_ref().and_then(|a| a.initramfs) {
+        Some(initramfs) => initramfs,
+        None => root_is_initramfs().unwrap_or_else(|err| {
+            unsafe {
+                libc::printf(
+                    b"Failed to detect the initramfs: %d\n\0".as_ptr() as *const libc::c_char,
+                    err as libc::c_int,
+                );
+            }
 
-                unreachable!()
-            })
-            .split(b'\n', false)
-            .unwrap_or_else(|err| {
-                unsafe {
-                    libc::printf(
-                        b"Failed to split /proc/mounts by line: %d\n\0".as_ptr()
-                            as *const libc::c_char,
-                        err as libc::c_int,
-                    );
-                }
+            exit_error(err);
 
-                exit_error(err);
+            unreachable!()
+        }),
+    };
 
-                unreachable!()
-            })
-            .iter()
-            .find_map(|raw_line| {
-                let unsplitted_line = core::str::from_utf8(raw_line.as_slice().unwrap()).unwrap();
-
-                let mut dev = "";
-                let mut mount = "";
-                for (idx, mount_component) in unsplitted_line.split(" ").enumerate() {
-                    match idx {
-                        0 => dev = mount_component,
-                        1 => mount = mount_component,
-                        _ => {}
-                    }
-                }
+    let context = Context::new(
+        rdname.as_ref().map(|name| name.as_str()),
+        cmdline.as_ref().map(|cmdline| cmdline.content.as_str()),
+    );
 
-                if mount == "/" {
-                    return Some(dev);
+    let config = match atomrootfsinit::read_whole_file(
+        atomrootfsinit::RDTAB_PATH,
+        atomrootfsinit::RDTAB_MAX_FILE_SIZE,
+    ) {
+        Ok(rdinit_content) => Config::new(atomrootfsinit::RDTAB_PATH, rdinit_content, &context)
+            .unwrap_or_else(|errors| {
+                for err in errors.iter() {
+                    err.print();
                 }
 
-                None
-            })
-            .map_or(false, |device| device == "rootfs");
-
-    let cmdline = read_cmdline();
-
-    let init = (match atomrootfsinit::read_whole_file(
-        atomrootfsinit::RDEXEC_PATH,
-        atomrootfsinit::RDEXEC_MAX_FILE_SIZE,
-    ) {
-        Ok(rdinit_content) => CStr::new(
-            core::str::from_utf8(rdinit_content.as_slice().unwrap_or(&[]))
-                .unwrap_or(atomrootfsinit::DEFAULT_INIT)
-                .trim(),
-        ),
+                // never boot a staged root whose mounts and hardening were not applied
+                exit_error(errors.iter().next().map_or(libc::EINVAL, |err| err.errno()));
+                unreachable!()
+            }),
         Err(err) => {
             unsafe {
                 libc::printf(
-                    b"Failed to open the rdinit file: %d -- default will be used\n\0".as_ptr()
-                        as *const libc::c_char,
+                    b"Failed to read configuration file: %d\n\0".as_ptr() as *const libc::c_char,
                     err as libc::c_int,
                 );
             }
-
-            match cmdline.as_ref().map_or(None, |a| a.init.clone()) {
-                Some(init) => CStr::new(init.as_str()),
-                None => CStr::new(atomrootfsinit::DEFAULT_INIT),
-            }
+            return exit_error(err);
         }
-    })
+    };
+
+    // every line of rdexec is an init command, tried in order before init= and the fallbacks
+    let rdexec = atomrootfsinit::read_whole_file(
+        atomrootfsinit::RDEXEC_PATH,
+        atomrootfsinit::RDEXEC_MAX_FILE_SIZE,
+    )
     .unwrap_or_else(|err| {
         unsafe {
             libc::printf(
-                b"Failed to allocate init: %d\n\0".as_ptr() as *const libc::c_char,
+                b"Failed to open the rdinit file: %d -- default will be used\n\0".as_ptr()
+                    as *const libc::c_char,
                 err as libc::c_int,
             );
         }
 
-        exit_error(err);
-
-        unreachable!()
+        Vec::default()
     });
 
+    // Wipe writable state before anything declared in rdtab gets mounted
+    let reset_triggered = atomrootfsinit::reset::reset_requested();
+    if reset_triggered || cmdline.as_ref().is_some_and(|a| a.factory_reset) {
+        unsafe {
+            libc::printf(b"Performing factory reset\n\0".as_ptr() as *const libc::c_char);
+        }
+
+        let wiped = match atomrootfsinit::reset::factory_reset(&config) {
+            Ok(wiped) => wiped,
+            Err(err) => {
+                unsafe {
+                    libc::printf(
+                        b"Failed to perform factory reset: %d\n\0".as_ptr()
+                            as *const libc::c_char,
+                        err as libc::c_int,
+                    );
+                }
+                return exit_error(err);
+            }
+        };
+
+        if wiped == 0 {
+            unsafe {
+                libc::printf(
+                    b"No x-reset directory to wipe: keeping the factory reset trigger\n\0"
+                        .as_ptr() as *const libc::c_char,
+                );
+            }
+        } else if reset_triggered {
+            if let Err(err) = atomrootfsinit::reset::consume_trigger() {
+                unsafe {
+                    libc::printf(
+                        b"Failed to remove the factory reset trigger: %d\n\0".as_ptr()
+                            as *const libc::c_char,
+                        err as libc::c_int,
+                    );
+                }
+            }
+        }
+    }
+
     // First pass: find sysfs, devtmpfs, and rootdev mounts
     let mut sys_mount_point: Option<&str> = None;
     let mut dev_mount_point: Option<&str> = None;
-    let mut rootdev_mount: Option<&Mountpoint> = None;
+    let mut rootdev_mount: Option<&Entry> = None;
     let mut rootfs_target = atomrootfsinit::SYSROOT;
 
-    for mount in config.iter_mounts() {
+    for entry in config.iter_entries() {
+        let mount = entry.mountpoint();
         // Track sysfs and devtmpfs mount points
         if let Some(fstype) = mount.fstype() {
             if fstype == "sysfs" {
@@ -1394,16 +1507,24 @@ fn main() {
         // Track rootdev mount for later processing
         if let Some(src) = mount.src() {
             if src == "rootdev" {
-                rootdev_mount = Some(mount);
+                rootdev_mount = Some(entry);
                 rootfs_target = mount.target();
             }
         }
     }
 
+    // Entries mounted so far, to be unmounted if a later one fails
+    let mut mounted = Vec::<&Entry>::default();
+
     // Mount sysfs and devtmpfs first if they exist (needed for PARTUUID resolution)
-    for mount in config.iter_mounts() {
+    for entry in config.iter_entries() {
+        let mount = entry.mountpoint();
         if let Some(fstype) = mount.fstype() {
             if fstype == "sysfs" || fstype == "devtmpfs" {
+                if !entry.conditions_met() {
+                    continue;
+                }
+
                 #[cfg(feature = "trace")]
                 unsafe {
                     libc::printf(
@@ -1412,15 +1533,30 @@ fn main() {
                     );
                 }
 
-                if let Err(err) = mount.mount(&None) {
+                if let Err(err) = entry.mount(&None) {
                     unsafe {
                         libc::printf(
-                            b"Failed to mount %s: %d\n\0".as_ptr() as *const libc::c_char,
+                            b"Failed to mount %s (%s): %d\n\0".as_ptr() as *const libc::c_char,
                             mount.target().as_ptr() as *const libc::c_char,
-                            err as libc::c_int,
+                            err.step().as_ptr() as *const libc::c_char,
+                            err.errno(),
                         );
                     }
-                    return exit_error(err);
+
+                    if !entry.nofail() {
+                        rollback_mounts(&mut mounted);
+                        return exit_error(err.errno());
+                    }
+
+                    continue;
+                }
+
+                if mount.attaches_mount() {
+                    if let Err(err) = mounted.push(entry) {
+                        let _ = direct_detach(mount.target());
+                        rollback_mounts(&mut mounted);
+                        return exit_error(err);
+                    }
                 }
             }
         }
@@ -1428,7 +1564,7 @@ fn main() {
 
     // Now resolve PARTUUID for rootdev if needed (sysfs should be mounted now)
     let mut resolved_rootdev: Option<CStr> = None;
-    if let Some(_mount) = rootdev_mount {
+    if let Some(rootdev_entry) = rootdev_mount {
         let rootfs = cmdline.as_ref().map_or(None, |a| a.root.clone());
 
         if let Some(ref rootfs_str) = rootfs {
@@ -1441,9 +1577,12 @@ fn main() {
                 let sys_mount = sys_mount_point.unwrap_or("/sys");
                 let dev_mount = dev_mount_point.unwrap_or("/dev");
 
-                if let Some(device_name_bytes) =
-                    find_device_by_partuuid(partuuid, sys_mount, dev_mount)
-                {
+                if let Some(device_name_bytes) = find_device_by_partuuid_with_timeout(
+                    partuuid,
+                    sys_mount,
+                    dev_mount,
+                    rootdev_entry.device_timeout(),
+                ) {
                     // Build device path using the devtmpfs mount point
                     let device_name_slice = match device_name_bytes.as_slice() {
                         Some(s) => s,
@@ -1568,6 +1707,7 @@ fn main() {
                             partuuid_cstr.inner(),
                         );
                     }
+                    rollback_mounts(&mut mounted);
                     return exit_error(libc::ENODEV);
                 }
             } else {
@@ -1581,7 +1721,9 @@ fn main() {
     }
 
     // Now mount all other mounts (including rootdev if it wasn't already processed)
-    for mount in config.iter_mounts() {
+    for entry in config.iter_entries() {
+        let mount = entry.mountpoint();
+
         // Skip sysfs and devtmpfs - already mounted
         if let Some(fstype) = mount.fstype() {
             if fstype == "sysfs" || fstype == "devtmpfs" {
@@ -1589,12 +1731,45 @@ fn main() {
             }
         }
 
+        // Skip entries whose [if-exists=...] conditions are not met
+        if !entry.conditions_met() {
+            #[cfg(feature = "trace")]
+            unsafe {
+                libc::printf(
+                    b"Skipping %s: condition not met\n\0".as_ptr() as *const libc::c_char,
+                    mount.target().as_ptr() as *const libc::c_char,
+                );
+            }
+
+            continue;
+        }
+
         let rootfs = if mount.src().map(|s| s == "rootdev").unwrap_or(false) {
             &resolved_rootdev
         } else {
             &None
         };
 
+        // Give slow devices (e.g. USB storage) some time to show up
+        if entry.device_timeout() > 0 {
+            let source = match rootfs {
+                Some(rootfs) => Some(rootfs.as_str()),
+                None => mount.src().filter(|src| src.starts_with('/')),
+            };
+
+            if let Some(source) = source {
+                if !atomrootfsinit::wait_for_path(source, entry.device_timeout()) {
+                    unsafe {
+                        libc::printf(
+                            b"Timed out waiting for the source of %s\n\0".as_ptr()
+                                as *const libc::c_char,
+                            mount.target().as_ptr() as *const libc::c_char,
+                        );
+                    }
+                }
+            }
+        }
+
         #[cfg(feature = "trace")]
         unsafe {
             libc::printf(
@@ -1603,26 +1778,49 @@ fn main() {
             );
         }
 
-        if let Err(err) = mount.mount(&rootfs) {
+        if let Err(err) = entry.mount(rootfs) {
             match &rootfs {
                 Some(rootfs) => unsafe {
                     libc::printf(
-                        b"Failed to mount %s from %s: %d\n\0".as_ptr() as *const libc::c_char,
+                        b"Failed to mount %s from %s (%s): %d\n\0".as_ptr() as *const libc::c_char,
                         mount.target().as_ptr() as *const libc::c_char,
                         rootfs.as_str().as_ptr() as *const libc::c_char,
-                        err as libc::c_int,
+                        err.step().as_ptr() as *const libc::c_char,
+                        err.errno(),
                     );
                 },
                 None => unsafe {
                     libc::printf(
-                        b"Failed to mount %s: %d\n\0".as_ptr() as *const libc::c_char,
+                        b"Failed to mount %s (%s): %d\n\0".as_ptr() as *const libc::c_char,
                         mount.target().as_ptr() as *const libc::c_char,
-                        err as libc::c_int,
+                        err.step().as_ptr() as *const libc::c_char,
+                        err.errno(),
                     );
                 },
             }
 
-            return exit_error(err);
+            if entry.nofail() {
+                unsafe {
+                    libc::printf(
+                        b"Mount of %s is optional (nofail): continuing\n\0".as_ptr()
+                            as *const libc::c_char,
+                        mount.target().as_ptr() as *const libc::c_char,
+                    );
+                }
+
+                continue;
+            }
+
+            rollback_mounts(&mut mounted);
+            return exit_error(err.errno());
+        }
+
+        if mount.attaches_mount() {
+            if let Err(err) = mounted.push(entry) {
+                let _ = direct_detach(mount.target());
+                rollback_mounts(&mut mounted);
+                return exit_error(err);
+            }
         }
     }
 
@@ -1639,10 +1837,163 @@ fn main() {
         unreachable!()
     });
 
+    // Links are created once every mount is in place, so that they can live on mounted storage
+    for link in config.iter_links() {
+        if !link.conditions_met() {
+            continue;
+        }
+
+        #[cfg(feature = "trace")]
+        unsafe {
+            libc::printf(
+                b"Linking %s\n\0".as_ptr() as *const libc::c_char,
+                link.path().as_ptr() as *const libc::c_char,
+            );
+        }
+
+        if let Err(err) = link.create(rootfs_target.as_str()) {
+            unsafe {
+                libc::printf(
+                    b"Failed to create link %s: %d\n\0".as_ptr() as *const libc::c_char,
+                    link.path().as_ptr() as *const libc::c_char,
+                    err as libc::c_int,
+                );
+            }
+
+            rollback_mounts(&mut mounted);
+            return exit_error(err);
+        }
+    }
+
+    // Pick the first init that can be executed in the new root: once switched, a
+    // failure to execute it could not be recovered from
+    let rdexec_commands = core::str::from_utf8(rdexec.as_slice().unwrap_or(&[]))
+        .unwrap_or("")
+        .lines()
+        .map(|line| line.trim())
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(Command::parse);
+
+    let other_commands = cmdline
+        .as_ref()
+        .and_then(|a| a.init.as_ref())
+        .map(|init| init.as_str())
+        .into_iter()
+        .chain(atomrootfsinit::FALLBACK_INITS.iter().copied())
+        .map(Command::new);
+
+    let mut init = None;
+
+    for candidate in rdexec_commands.chain(other_commands) {
+        let command = match candidate {
+            Ok(command) => command,
+            Err(err) => {
+                unsafe {
+                    libc::printf(
+                        b"Skipping an invalid init command: %d\n\0".as_ptr()
+                            as *const libc::c_char,
+                        err as libc::c_int,
+                    );
+                }
+
+                continue;
+            }
+        };
+
+        match check_program(rootfs_target.as_str(), command.program()) {
+            Ok(()) => {
+                init = Some(command);
+                break;
+            }
+            Err(err) => {
+                let program = CStr::new(command.program());
+
+                unsafe {
+                    libc::printf(
+                        b"Skipping init %s: %d\n\0".as_ptr() as *const libc::c_char,
+                        program.as_ref().map_or(
+                            b"(unprintable name)\0".as_ptr() as *const libc::c_char,
+                            |program| program.inner(),
+                        ),
+                        err as libc::c_int,
+                    );
+                }
+            }
+        }
+    }
+
+    let Some(mut init) = init else {
+        unsafe {
+            libc::printf(
+                b"No executable init found in %s\n\0".as_ptr() as *const libc::c_char,
+                rootfs_target.inner(),
+            );
+        }
+
+        rollback_mounts(&mut mounted);
+        return exit_error(libc::ENOENT);
+    };
+
+    // the kernel passes the command line arguments and variables meant for init to us
+    if let Err(err) = init
+        .forward_arguments()
+        .and_then(|_| init.inherit_environment())
+    {
+        unsafe {
+            libc::printf(
+                b"Failed to forward the arguments and environment to init: %d\n\0".as_ptr()
+                    as *const libc::c_char,
+                err as libc::c_int,
+            );
+        }
+    }
+
+    // Mounts of the early root (e.g. the kernel's devtmpfs) would be lost with it
+    let new_root = new_root_path(initramfs, rootfs_target.as_str());
+    let move_mounts = cmdline
+        .as_ref()
+        .and_then(|a| a.move_mounts.as_ref())
+        .map_or(atomrootfsinit::DEFAULT_MOVE_MOUNTS, |paths| paths.as_str());
+
+    for path in move_mounts.split(',').filter(|path| path.starts_with('/')) {
+        let Ok(path_str) = CStr::new(path) else {
+            continue;
+        };
+
+        match move_into_new_root(path, new_root) {
+            #[cfg(feature = "trace")]
+            Ok(true) => unsafe {
+                libc::printf(
+                    b"Moved %s into the new root\n\0".as_ptr() as *const libc::c_char,
+                    path_str.inner(),
+                );
+            },
+            Ok(_) => {}
+            Err(err) => unsafe {
+                libc::printf(
+                    b"Failed to move %s into the new root: %d\n\0".as_ptr()
+                        as *const libc::c_char,
+                    path_str.inner(),
+                    err as libc::c_int,
+                );
+            },
+        }
+    }
+
     // ensure memory is released before switch_root
+    drop(mounted);
     drop(config);
 
-    if let Err(err) = switch_root(initramfs, rootfs_target.as_str(), ".", init.as_str()) {
+    // the initramfs is deleted to free the memory it holds, unless asked otherwise
+    let free_initramfs = !cmdline.as_ref().is_some_and(|a| a.keep_initramfs);
+
+    if let Err(err) = switch_root(
+        initramfs,
+        free_initramfs,
+        rootfs_target.as_str(),
+        ".",
+        &init,
+    ) {
         unsafe {
             libc::printf(
                 b"Failed to switch_root to %s: %d\n\0".as_ptr() as *const libc::c_char,
@@ -1658,6 +2009,34 @@ fn main() {
     // that replaces the current program with the specified one.
 }
 
+/**
+ * Lazily unmount the given entries in reverse order, so that the recovery shell or
+ * a retry starts from a clean slate rather than a partially assembled root.
+ */
+fn rollback_mounts(mounted: &mut Vec<&Entry>) {
+    while let Some(entry) = mounted.pop() {
+        let target = entry.mountpoint().target();
+
+        #[cfg(feature = "trace")]
+        unsafe {
+            libc::printf(
+                b"Unmounting %s\n\0".as_ptr() as *const libc::c_char,
+                target.as_ptr() as *const libc::c_char,
+            );
+        }
+
+        if let Err(err) = direct_detach(target) {
+            unsafe {
+                libc::printf(
+                    b"Failed to unmount %s: %d\n\0".as_ptr() as *const libc::c_char,
+                    target.as_ptr() as *const libc::c_char,
+                    err as libc::c_int,
+                );
+            }
+        }
+    }
+}
+
 fn exit_error(err: libc::c_int) {
     #[cfg(feature = "droptosh")]
     if let Err(err) = atomrootfsinit::switch_root::execute("/bin/sh") {
@@ -0,0 +1,137 @@
+use crate::string::CStr;
+
+/// Owner to be given to newly created paths: None leaves the corresponding id unchanged.
+#[derive(Copy, Clone, Debug, PartialEq, Default)]
+pub struct Owner {
+    pub uid: Option<libc::uid_t>,
+    pub gid: Option<libc::gid_t>,
+}
+
+impl Owner {
+    fn apply(&self, path: &CStr) -> Result<(), libc::c_int> {
+        if self.uid.is_none() && self.gid.is_none() {
+            return Ok(());
+        }
+
+        unsafe {
+            /*
+             * On success, zero is returned. On error, -1 is returned, and errno
+             * is set to indicate the error.
+             *
+             * An id of -1 leaves the corresponding id unchanged.
+             */
+            if libc::lchown(
+                path.inner(),
+                self.uid.unwrap_or(libc::uid_t::MAX),
+                self.gid.unwrap_or(libc::gid_t::MAX),
+            ) != 0
+            {
+                return Err(*libc::__errno_location());
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Set the mode of a newly created path: mkdir(2) and open(2) apply the umask.
+fn set_mode(path: &CStr, mode: libc::mode_t) -> Result<(), libc::c_int> {
+    unsafe {
+        /*
+         * On success, zero is returned. On error, -1 is returned, and errno
+         * is set to indicate the error.
+         */
+        if libc::chmod(path.inner(), mode) != 0 {
+            return Err(*libc::__errno_location());
+        }
+    }
+
+    Ok(())
+}
+
+/**
+ * Create the given directory and every missing parent, like mkdir -p.
+ *
+ * Only directories created by this call get the given mode and owner.
+ *
+ * @param path an absolute path
+ */
+pub fn create_directories(path: &str, mode: libc::mode_t, owner: Owner) -> Result<(), libc::c_int> {
+    if !path.starts_with('/') {
+        return Err(libc::EINVAL);
+    }
+
+    let path = path.trim_end_matches('/');
+
+    // the root directory always exists
+    if path.is_empty() {
+        return Ok(());
+    }
+
+    for (idx, _) in path
+        .match_indices('/')
+        .skip(1)
+        .chain(core::iter::once((path.len(), "")))
+    {
+        let component = CStr::new(&path[..idx])?;
+
+        unsafe {
+            /*
+             * On success, zero is returned. On error, -1 is returned, and errno
+             * is set to indicate the error.
+             */
+            if libc::mkdir(component.inner(), mode) != 0 {
+                match *libc::__errno_location() {
+                    libc::EEXIST => continue,
+                    err => return Err(err),
+                }
+            }
+        }
+
+        // after the owner, as changing it clears the set-user-ID and set-group-ID bits
+        owner.apply(&component)?;
+        set_mode(&component, mode)?;
+    }
+
+    Ok(())
+}
+
+/**
+ * Create an empty regular file at the given path unless it already exists,
+ * creating missing parent directories with mode 0755.
+ *
+ * The file gets the given mode and owner only if created by this call.
+ *
+ * @param path an absolute path
+ */
+pub fn create_file(path: &str, mode: libc::mode_t, owner: Owner) -> Result<(), libc::c_int> {
+    let parent = match path.rfind('/') {
+        Some(0) => "/",
+        Some(idx) => &path[..idx],
+        None => return Err(libc::EINVAL),
+    };
+
+    create_directories(parent, 0o755, owner)?;
+
+    let path_str = CStr::new(path)?;
+
+    let fd = unsafe {
+        libc::open(
+            path_str.inner(),
+            libc::O_WRONLY | libc::O_CREAT | libc::O_EXCL | libc::O_CLOEXEC,
+            mode as libc::c_uint,
+        )
+    };
+
+    if fd < 0 {
+        return match unsafe { *libc::__errno_location() } {
+            libc::EEXIST => Ok(()),
+            err => Err(err),
+        };
+    }
+
+    unsafe { libc::close(fd) };
+
+    owner.apply(&path_str)?;
+    set_mode(&path_str, mode)
+}
@@ -1,6 +1,23 @@
 use core::ptr;
 
-use crate::string::CStr;
+use crate::idmap::IdMap;
+use crate::string::{unescape_octal, CStr};
+use crate::vector::Vec;
+
+const FSOPEN_CLOEXEC: libc::c_uint = 0x1;
+
+const FSCONFIG_SET_FLAG: libc::c_uint = 0;
+
+const FSCONFIG_SET_STRING: libc::c_uint = 1;
+
+const FSCONFIG_CMD_CREATE: libc::c_uint = 6;
+
+const FSMOUNT_CLOEXEC: libc::c_uint = 0x1;
+
+const MOVE_MOUNT_F_EMPTY_PATH: libc::c_uint = 0x4;
+
+/// Maximum length of a message read from the log of a filesystem context.
+const FS_LOG_MESSAGE_MAX: usize = 512;
 
 #[derive(Copy, Clone, Debug, PartialEq)]
 pub enum MountFlag {
@@ -10,6 +27,7 @@ pub enum MountFlag {
     Slave,
     Unbindable,
     Recursive,
+    RecursivePropagation,
     DirSync,
     Lazytime,
     NoAccessTime,
@@ -22,6 +40,11 @@ pub enum MountFlag {
     Synchronous,
     Remount,
     Move,
+    NoDirAccessTime,
+    StrictAccessTime,
+    NoSymFollow,
+    MandatoryLock,
+    IVersion,
 }
 
 #[derive(Copy, Clone, Debug, PartialEq, Default)]
@@ -32,6 +55,7 @@ pub struct MountpointFlags {
     slave: bool,
     unbindable: bool,
     recursive: bool,
+    recursive_propagation: bool,
     dirsync: bool,
     lazytime: bool,
     no_access_time: bool,
@@ -44,6 +68,11 @@ pub struct MountpointFlags {
     synchronous: bool,
     remount: bool,
     movep: bool,
+    no_dir_access_time: bool,
+    strict_access_time: bool,
+    no_sym_follow: bool,
+    mandatory_lock: bool,
+    i_version: bool,
 }
 
 impl MountpointFlags {
@@ -57,36 +86,79 @@ impl MountpointFlags {
         mountpoint_flags
     }
 
+    fn field_mut(&mut self, flag: MountFlag) -> &mut bool {
+        match flag {
+            MountFlag::Bind => &mut self.bind,
+            MountFlag::Shared => &mut self.shared,
+            MountFlag::Private => &mut self.private,
+            MountFlag::Slave => &mut self.slave,
+            MountFlag::Unbindable => &mut self.unbindable,
+            MountFlag::Recursive => &mut self.recursive,
+            MountFlag::RecursivePropagation => &mut self.recursive_propagation,
+            MountFlag::DirSync => &mut self.dirsync,
+            MountFlag::Lazytime => &mut self.lazytime,
+            MountFlag::NoAccessTime => &mut self.no_access_time,
+            MountFlag::NoDev => &mut self.no_dev,
+            MountFlag::NoExec => &mut self.no_exec,
+            MountFlag::NoSUID => &mut self.no_suid,
+            MountFlag::ReadOnly => &mut self.read_only,
+            MountFlag::RelativeAccessTime => &mut self.relative_access_time,
+            MountFlag::Silent => &mut self.silent,
+            MountFlag::Synchronous => &mut self.synchronous,
+            MountFlag::Remount => &mut self.remount,
+            MountFlag::Move => &mut self.movep,
+            MountFlag::NoDirAccessTime => &mut self.no_dir_access_time,
+            MountFlag::StrictAccessTime => &mut self.strict_access_time,
+            MountFlag::NoSymFollow => &mut self.no_sym_follow,
+            MountFlag::MandatoryLock => &mut self.mandatory_lock,
+            MountFlag::IVersion => &mut self.i_version,
+        }
+    }
+
     pub fn set(&mut self, flag: MountFlag) {
+        *self.field_mut(flag) = true;
+    }
+
+    pub fn clear(&mut self, flag: MountFlag) {
+        *self.field_mut(flag) = false;
+    }
+
+    pub fn is_set(&self, flag: MountFlag) -> bool {
         match flag {
-            MountFlag::Bind => self.bind = true,
-            MountFlag::Shared => self.shared = true,
-            MountFlag::Private => self.private = true,
-            MountFlag::Slave => self.slave = true,
-            MountFlag::Unbindable => self.unbindable = true,
-            MountFlag::Recursive => self.recursive = true,
-            MountFlag::DirSync => self.dirsync = true,
-            MountFlag::Lazytime => self.lazytime = true,
-            MountFlag::NoAccessTime => self.no_access_time = true,
-            MountFlag::NoDev => self.no_dev = true,
-            MountFlag::NoExec => self.no_exec = true,
-            MountFlag::NoSUID => self.no_suid = true,
-            MountFlag::ReadOnly => self.read_only = true,
-            MountFlag::RelativeAccessTime => self.relative_access_time = true,
-            MountFlag::Silent => self.silent = true,
-            MountFlag::Synchronous => self.synchronous = true,
-            MountFlag::Remount => self.remount = true,
-            MountFlag::Move => self.movep = true,
-        }
-    }
-
-    pub(crate) fn flags(&self) -> libc::c_ulong {
+            MountFlag::Bind => self.bind,
+            MountFlag::Shared => self.shared,
+            MountFlag::Private => self.private,
+            MountFlag::Slave => self.slave,
+            MountFlag::Unbindable => self.unbindable,
+            MountFlag::Recursive => self.recursive,
+            MountFlag::RecursivePropagation => self.recursive_propagation,
+            MountFlag::DirSync => self.dirsync,
+            MountFlag::Lazytime => self.lazytime,
+            MountFlag::NoAccessTime => self.no_access_time,
+            MountFlag::NoDev => self.no_dev,
+            MountFlag::NoExec => self.no_exec,
+            MountFlag::NoSUID => self.no_suid,
+            MountFlag::ReadOnly => self.read_only,
+            MountFlag::RelativeAccessTime => self.relative_access_time,
+            MountFlag::Silent => self.silent,
+            MountFlag::Synchronous => self.synchronous,
+            MountFlag::Remount => self.remount,
+            MountFlag::Move => self.movep,
+            MountFlag::NoDirAccessTime => self.no_dir_access_time,
+            MountFlag::StrictAccessTime => self.strict_access_time,
+            MountFlag::NoSymFollow => self.no_sym_follow,
+            MountFlag::MandatoryLock => self.mandatory_lock,
+            MountFlag::IVersion => self.i_version,
+        }
+    }
+
+    /**
+     * Flags of the mount(2) call attaching the filesystem: every flag but the propagation
+     * type. MS_REC is only meaningful here to bind a whole tree.
+     */
+    pub(crate) fn mount_flags(&self) -> libc::c_ulong {
         (self.bind as libc::c_ulong * libc::MS_BIND)
-            | (self.shared as libc::c_ulong * libc::MS_SHARED)
-            | (self.private as libc::c_ulong * libc::MS_PRIVATE)
-            | (self.slave as libc::c_ulong * libc::MS_SLAVE)
-            | (self.unbindable as libc::c_ulong * libc::MS_UNBINDABLE)
-            | (self.recursive as libc::c_ulong * libc::MS_REC)
+            | ((self.bind && self.recursive) as libc::c_ulong * libc::MS_REC)
             | (self.dirsync as libc::c_ulong * libc::MS_DIRSYNC)
             | (self.lazytime as libc::c_ulong * libc::MS_LAZYTIME)
             | (self.no_access_time as libc::c_ulong * libc::MS_NOATIME)
@@ -99,6 +171,135 @@ impl MountpointFlags {
             | (self.synchronous as libc::c_ulong * libc::MS_SYNCHRONOUS)
             | (self.remount as libc::c_ulong * libc::MS_REMOUNT)
             | (self.movep as libc::c_ulong * libc::MS_MOVE)
+            | (self.no_dir_access_time as libc::c_ulong * libc::MS_NODIRATIME)
+            | (self.strict_access_time as libc::c_ulong * libc::MS_STRICTATIME)
+            | (self.no_sym_follow as libc::c_ulong * libc::MS_NOSYMFOLLOW)
+            | (self.mandatory_lock as libc::c_ulong * libc::MS_MANDLOCK)
+            | (self.i_version as libc::c_ulong * libc::MS_I_VERSION)
+    }
+
+    /**
+     * Flags that apply to a single mount rather than to the filesystem:
+     * the kernel ignores them on the initial MS_BIND mount.
+     */
+    pub(crate) fn per_mount_flags(&self) -> libc::c_ulong {
+        (self.read_only as libc::c_ulong * libc::MS_RDONLY)
+            | (self.no_suid as libc::c_ulong * libc::MS_NOSUID)
+            | (self.no_dev as libc::c_ulong * libc::MS_NODEV)
+            | (self.no_exec as libc::c_ulong * libc::MS_NOEXEC)
+            | (self.no_access_time as libc::c_ulong * libc::MS_NOATIME)
+            | (self.no_dir_access_time as libc::c_ulong * libc::MS_NODIRATIME)
+            | (self.relative_access_time as libc::c_ulong * libc::MS_RELATIME)
+            | (self.strict_access_time as libc::c_ulong * libc::MS_STRICTATIME)
+            | (self.no_sym_follow as libc::c_ulong * libc::MS_NOSYMFOLLOW)
+    }
+
+    /**
+     * Flags that apply to the superblock, named as fsconfig(2) flags (NUL-terminated).
+     *
+     * Only the ones the VFS handles for every filesystem: see has_mount_only_flags().
+     */
+    pub(crate) fn superblock_options(&self) -> impl Iterator<Item = &'static [u8]> {
+        [
+            (self.read_only, b"ro\0".as_slice()),
+            (self.synchronous, b"sync\0".as_slice()),
+            (self.dirsync, b"dirsync\0".as_slice()),
+            (self.lazytime, b"lazytime\0".as_slice()),
+            (self.mandatory_lock, b"mand\0".as_slice()),
+        ]
+        .into_iter()
+        .filter_map(|(set, option)| set.then_some(option))
+    }
+
+    /**
+     * Whether a superblock flag without a generic fsconfig(2) parameter is set: silent
+     * and iversion are passed to the parser of the filesystem, that rejects them.
+     */
+    pub(crate) fn has_mount_only_flags(&self) -> bool {
+        self.silent || self.i_version
+    }
+
+    /// The per-mount flags as attributes for mount_setattr(2).
+    pub(crate) fn mount_attr(&self) -> libc::mount_attr {
+        let mut attr: libc::mount_attr = unsafe { core::mem::zeroed() };
+
+        attr.attr_set = (self.read_only as u64 * libc::MOUNT_ATTR_RDONLY)
+            | (self.no_suid as u64 * libc::MOUNT_ATTR_NOSUID)
+            | (self.no_dev as u64 * libc::MOUNT_ATTR_NODEV)
+            | (self.no_exec as u64 * libc::MOUNT_ATTR_NOEXEC)
+            | (self.no_dir_access_time as u64 * libc::MOUNT_ATTR_NODIRATIME)
+            | (self.no_sym_follow as u64 * libc::MOUNT_ATTR_NOSYMFOLLOW);
+
+        // the access time update mode is a single value, not a set of flags
+        let atime = match (
+            self.no_access_time,
+            self.strict_access_time,
+            self.relative_access_time,
+        ) {
+            (true, _, _) => Some(libc::MOUNT_ATTR_NOATIME),
+            (false, true, _) => Some(libc::MOUNT_ATTR_STRICTATIME),
+            (false, false, true) => Some(libc::MOUNT_ATTR_RELATIME),
+            (false, false, false) => None,
+        };
+
+        if let Some(atime) = atime {
+            attr.attr_set |= atime;
+            attr.attr_clr |= libc::MOUNT_ATTR__ATIME;
+        }
+
+        attr
+    }
+
+    /**
+     * Flags of the mount(2) call changing the propagation type, which the kernel only
+     * accepts on their own (with MS_REC to change the whole tree, independently of
+     * a recursive bind): 0 when no type has been requested.
+     */
+    pub(crate) fn propagation_flags(&self) -> libc::c_ulong {
+        let propagation = (self.shared as libc::c_ulong * libc::MS_SHARED)
+            | (self.private as libc::c_ulong * libc::MS_PRIVATE)
+            | (self.slave as libc::c_ulong * libc::MS_SLAVE)
+            | (self.unbindable as libc::c_ulong * libc::MS_UNBINDABLE);
+
+        match propagation {
+            0 => 0,
+            propagation => {
+                propagation | (self.recursive_propagation as libc::c_ulong * libc::MS_REC)
+            }
+        }
+    }
+}
+
+/// The step of a mount operation that failed, with the errno it failed with.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum MountError {
+    /// Creating the missing mount target.
+    Target(libc::c_int),
+    /// Attaching the filesystem.
+    Mount(libc::c_int),
+    /// Applying the per-mount flags to a bind mount.
+    Remount(libc::c_int),
+    /// Changing the propagation type of the mount.
+    Propagation(libc::c_int),
+}
+
+impl MountError {
+    pub fn errno(&self) -> libc::c_int {
+        match *self {
+            Self::Target(err) | Self::Mount(err) | Self::Remount(err) | Self::Propagation(err) => {
+                err
+            }
+        }
+    }
+
+    /// NUL-terminated name of the failed step, to be printed on the console.
+    pub fn step(&self) -> &'static [u8] {
+        match self {
+            Self::Target(_) => b"creating the target\0",
+            Self::Mount(_) => b"mount\0",
+            Self::Remount(_) => b"bind remount\0",
+            Self::Propagation(_) => b"propagation change\0",
+        }
     }
 }
 
@@ -110,6 +311,7 @@ pub struct Mountpoint {
     data: *const libc::c_void,
     data_len: usize,
     flags: MountpointFlags,
+    idmap: Option<IdMap>,
 }
 
 impl Drop for Mountpoint {
@@ -165,10 +367,73 @@ impl Mountpoint {
             data,
             data_len,
             flags,
+            idmap: None,
         })
     }
 
-    pub fn mount(&self, rootdev: &Option<CStr>) -> Result<(), libc::c_int> {
+    /// Make this an ID-mapped mount, shifting the owners of files as seen through it.
+    pub fn set_idmap(&mut self, idmap: IdMap) {
+        self.idmap = Some(idmap);
+    }
+
+    /// Whether this only changes the propagation type of an existing mount: no filesystem
+    /// and no flag other than the propagation type are given.
+    fn changes_propagation_only(&self) -> bool {
+        self.flags.propagation_flags() != 0
+            && (self.flags.mount_flags() & !libc::MS_REC) == 0
+            && self.data.is_null()
+            && self.fstype().is_none_or(|fstype| fstype == "none")
+    }
+
+    /// Whether mounting this attaches a new mount at the target, as opposed to changing
+    /// (remount, propagation type) or moving an existing one.
+    pub fn attaches_mount(&self) -> bool {
+        !self.changes_propagation_only()
+            && !self.flags.is_set(MountFlag::Remount)
+            && !self.flags.is_set(MountFlag::Move)
+    }
+
+    /// Whether this is a bind mount whose per-mount flags need a remount to be applied.
+    fn needs_bind_remount(&self) -> bool {
+        self.flags.is_set(MountFlag::Bind)
+            && !self.flags.is_set(MountFlag::Remount)
+            && self.flags.per_mount_flags() != 0
+    }
+
+    /**
+     * Mount the filesystem, then change its propagation type if requested:
+     * the kernel does not apply a propagation type given together with other flags.
+     *
+     * Bind mounts are remounted to apply flags such as ro, nosuid, nodev and noexec,
+     * that the kernel ignores on the initial bind.
+     *
+     * @param rootdev the device to be used in place of the rootdev source
+     */
+    pub fn mount(&self, rootdev: &Option<CStr>) -> Result<(), MountError> {
+        if !self.changes_propagation_only() {
+            self.attach(rootdev).map_err(MountError::Mount)?;
+        }
+
+        if self.needs_bind_remount() {
+            self.remount_bind().map_err(MountError::Remount)?;
+        }
+
+        self.change_propagation().map_err(MountError::Propagation)
+    }
+
+    /// Whether this creates a new filesystem instance, that can be mounted with a
+    /// filesystem context, as opposed to a bind, a move or a remount of an existing mount.
+    fn creates_filesystem(&self) -> bool {
+        !self.flags.is_set(MountFlag::Bind)
+            && !self.flags.is_set(MountFlag::Move)
+            && !self.flags.is_set(MountFlag::Remount)
+            && self.fstype().is_some_and(|fstype| fstype != "none")
+            && self
+                .data()
+                .is_none_or(|data| core::str::from_utf8(data).is_ok())
+    }
+
+    fn attach(&self, rootdev: &Option<CStr>) -> Result<(), libc::c_int> {
         let src = match &self.src {
             Some(ptr) => match ptr.as_str() {
                 "rootdev" => match &rootdev {
@@ -180,6 +445,22 @@ impl Mountpoint {
             None => core::ptr::null() as *const libc::c_char,
         };
 
+        if self.idmap.is_some() {
+            return self.attach_idmapped(src);
+        }
+
+        // the new mount API reports why a filesystem refused to mount: use it unless
+        // the kernel predates it (Linux 5.2), a seccomp filter blocks it or a flag
+        // can only be given to mount(2)
+        if self.creates_filesystem() && !self.flags.has_mount_only_flags() {
+            return self.attach_fs_context(src, Some(&|| self.attach_legacy(src)));
+        }
+
+        self.attach_legacy(src)
+    }
+
+    /// Attach the mount with mount(2).
+    fn attach_legacy(&self, src: *const libc::c_char) -> Result<(), libc::c_int> {
         let fstype = match &self.fstype {
             Some(ptr) => ptr.inner(),
             None => core::ptr::null() as *const libc::c_char,
@@ -193,7 +474,7 @@ impl Mountpoint {
                 src,
                 self.target.inner(),
                 fstype,
-                self.flags.flags(),
+                self.flags.mount_flags(),
                 self.data,
             ) != 0
             {
@@ -204,6 +485,241 @@ impl Mountpoint {
         Ok(())
     }
 
+    /**
+     * Attach an ID-mapped mount: the mapping can only be set on a detached mount,
+     * i.e. a new filesystem instance from fsmount(2) or a bind mount cloned with
+     * open_tree(2), that is moved to the target once mapped.
+     *
+     * There is no fallback to mount(2): ENOSYS is returned on kernels older than 5.12.
+     */
+    fn attach_idmapped(&self, src: *const libc::c_char) -> Result<(), libc::c_int> {
+        if self.creates_filesystem() {
+            return self.attach_fs_context(src, None);
+        }
+
+        if !self.flags.is_set(MountFlag::Bind) || src.is_null() {
+            return Err(libc::EINVAL);
+        }
+
+        let recursive = self.flags.is_set(MountFlag::Recursive);
+
+        let tree_fd = unsafe {
+            /*
+             * On success, a new file descriptor is returned. On error, -1 is returned,
+             * and errno is set to indicate the error.
+             */
+            libc::syscall(
+                libc::SYS_open_tree,
+                libc::AT_FDCWD,
+                src,
+                libc::OPEN_TREE_CLONE
+                    | libc::OPEN_TREE_CLOEXEC
+                    | match recursive {
+                        true => libc::AT_RECURSIVE as libc::c_uint,
+                        false => 0,
+                    },
+            ) as libc::c_int
+        };
+
+        if tree_fd < 0 {
+            return Err(unsafe { *libc::__errno_location() });
+        }
+
+        // the per-mount flags are applied together with the mapping
+        let result = self.move_detached(tree_fd, self.flags.mount_attr(), recursive);
+
+        unsafe { libc::close(tree_fd) };
+
+        result
+    }
+
+    /**
+     * Move a detached mount to the target, setting the given attributes and the
+     * ID mapping, if any, on it first.
+     */
+    fn move_detached(
+        &self,
+        mnt_fd: libc::c_int,
+        attr: libc::mount_attr,
+        recursive: bool,
+    ) -> Result<(), libc::c_int> {
+        if let Some(idmap) = &self.idmap {
+            set_idmap_attributes(mnt_fd, idmap, attr, recursive)?;
+        }
+
+        unsafe {
+            /*
+             * On success, zero is returned. On error, -1 is returned, and errno
+             * is set to indicate the error.
+             */
+            match libc::syscall(
+                libc::SYS_move_mount,
+                mnt_fd,
+                b"\0".as_ptr() as *const libc::c_char,
+                libc::AT_FDCWD,
+                self.target.inner(),
+                MOVE_MOUNT_F_EMPTY_PATH,
+            ) {
+                0 => Ok(()),
+                _ => Err(*libc::__errno_location()),
+            }
+        }
+    }
+
+    /**
+     * Mount a new filesystem instance with fsopen(2), fsconfig(2), fsmount(2) and
+     * move_mount(2), feeding every option separately.
+     *
+     * When the kernel lacks the new mount API or its support for nosymfollow (Linux 5.14),
+     * or it is forbidden (e.g. by seccomp), fallback is used instead if given: ENOSYS or
+     * EPERM is returned otherwise. The silent and iversion flags are not applied.
+     *
+     * The messages logged by the filesystem are printed on the console only if the
+     * mount finally fails.
+     */
+    fn attach_fs_context(
+        &self,
+        src: *const libc::c_char,
+        fallback: Option<&dyn Fn() -> Result<(), libc::c_int>>,
+    ) -> Result<(), libc::c_int> {
+        let fstype = self.fstype.as_ref().ok_or(libc::EINVAL)?;
+
+        let fs_fd = unsafe {
+            /*
+             * On success, a new file descriptor is returned. On error, -1 is returned,
+             * and errno is set to indicate the error.
+             */
+            libc::syscall(libc::SYS_fsopen, fstype.inner(), FSOPEN_CLOEXEC) as libc::c_int
+        };
+
+        if fs_fd < 0 {
+            return match (unsafe { *libc::__errno_location() }, fallback) {
+                (libc::ENOSYS | libc::EPERM, Some(fallback)) => fallback(),
+                (err, _) => Err(err),
+            };
+        }
+
+        let result = match (self.mount_fs_context(fs_fd, src), fallback) {
+            (Err(libc::ENOSYS | libc::EPERM), Some(fallback)) => fallback(),
+            (result, _) => result,
+        };
+
+        if result.is_err() {
+            print_fs_log(fs_fd, self.target.as_str());
+        }
+
+        unsafe { libc::close(fs_fd) };
+
+        result
+    }
+
+    fn mount_fs_context(
+        &self,
+        fs_fd: libc::c_int,
+        src: *const libc::c_char,
+    ) -> Result<(), libc::c_int> {
+        if !src.is_null() {
+            fsconfig(
+                fs_fd,
+                FSCONFIG_SET_STRING,
+                b"source\0".as_ptr() as *const libc::c_char,
+                src,
+            )?;
+        }
+
+        for option in self.flags.superblock_options() {
+            fsconfig(
+                fs_fd,
+                FSCONFIG_SET_FLAG,
+                option.as_ptr() as *const libc::c_char,
+                ptr::null(),
+            )?;
+        }
+
+        let data = self
+            .data()
+            .map(|data| core::str::from_utf8(data).unwrap_or(""))
+            .unwrap_or("")
+            .trim_end_matches('\0');
+
+        for option in data.split(',').filter(|option| !option.is_empty()) {
+            match option.split_once('=') {
+                Some((key, value)) => {
+                    let key = CStr::new(key)?;
+                    let value = CStr::new(value)?;
+                    fsconfig(fs_fd, FSCONFIG_SET_STRING, key.inner(), value.inner())?;
+                }
+                None => {
+                    let key = CStr::new(option)?;
+                    fsconfig(fs_fd, FSCONFIG_SET_FLAG, key.inner(), ptr::null())?;
+                }
+            }
+        }
+
+        fsconfig(fs_fd, FSCONFIG_CMD_CREATE, ptr::null(), ptr::null())?;
+
+        let attr_set = self.flags.mount_attr().attr_set;
+
+        let mnt_fd = unsafe {
+            /*
+             * On success, a new file descriptor is returned. On error, -1 is returned,
+             * and errno is set to indicate the error.
+             */
+            libc::syscall(
+                libc::SYS_fsmount,
+                fs_fd,
+                FSMOUNT_CLOEXEC,
+                attr_set as libc::c_uint,
+            ) as libc::c_int
+        };
+
+        if mnt_fd < 0 {
+            return match unsafe { *libc::__errno_location() } {
+                // before Linux 5.14 only mount(2) knows nosymfollow
+                libc::EINVAL if attr_set & libc::MOUNT_ATTR_NOSYMFOLLOW != 0 => Err(libc::ENOSYS),
+                err => Err(err),
+            };
+        }
+
+        // the flags were given to fsmount(2) already
+        let result = self.move_detached(mnt_fd, unsafe { core::mem::zeroed() }, false);
+
+        unsafe { libc::close(mnt_fd) };
+
+        result
+    }
+
+    /// Apply the per-mount flags to a bind mount: to the whole tree for a recursive bind.
+    fn remount_bind(&self) -> Result<(), libc::c_int> {
+        match self.flags.is_set(MountFlag::Recursive) {
+            true => set_tree_flags(self.target.as_str(), &self.flags),
+            false => remount_per_mount_flags(&self.target, self.flags.per_mount_flags()),
+        }
+    }
+
+    fn change_propagation(&self) -> Result<(), libc::c_int> {
+        let propagation_flags = self.flags.propagation_flags();
+        if propagation_flags == 0 {
+            return Ok(());
+        }
+
+        unsafe {
+            // mount(NULL, "target", NULL, MS_SHARED | MS_REC, NULL)
+            if libc::mount(
+                core::ptr::null(),
+                self.target.inner(),
+                core::ptr::null(),
+                propagation_flags,
+                core::ptr::null(),
+            ) != 0
+            {
+                return Err(*libc::__errno_location());
+            }
+        }
+
+        Ok(())
+    }
+
     pub fn target(&self) -> &str {
         let slice = unsafe {
             core::slice::from_raw_parts(self.target.inner() as *const u8, self.target.strlen())
@@ -236,6 +752,10 @@ impl Mountpoint {
         }
     }
 
+    pub fn flags(&self) -> MountpointFlags {
+        self.flags
+    }
+
     pub fn data(&self) -> Option<&[u8]> {
         let data_ptr = self.data as *const u8;
         if data_ptr.is_null() || self.data_len == 0 {
@@ -246,6 +766,255 @@ impl Mountpoint {
     }
 }
 
+fn fsconfig(
+    fs_fd: libc::c_int,
+    cmd: libc::c_uint,
+    key: *const libc::c_char,
+    value: *const libc::c_char,
+) -> Result<(), libc::c_int> {
+    unsafe {
+        /*
+         * On success, zero is returned. On error, -1 is returned, and errno
+         * is set to indicate the error.
+         */
+        if libc::syscall(libc::SYS_fsconfig, fs_fd, cmd, key, value, 0 as libc::c_int) != 0 {
+            return Err(*libc::__errno_location());
+        }
+    }
+
+    Ok(())
+}
+
+/// Print the messages (e.g. "e ext4: Unrecognized mount option") logged in a filesystem context.
+fn print_fs_log(fs_fd: libc::c_int, target: &str) {
+    let target = CStr::new(target).ok();
+
+    loop {
+        let mut message = [0u8; FS_LOG_MESSAGE_MAX + 1];
+
+        // every read returns a single message, until ENODATA
+        let len = unsafe {
+            libc::read(
+                fs_fd,
+                message.as_mut_ptr() as *mut libc::c_void,
+                FS_LOG_MESSAGE_MAX,
+            )
+        };
+
+        if len <= 0 {
+            break;
+        }
+
+        if message[len as usize - 1] == b'\n' {
+            message[len as usize - 1] = 0;
+        }
+
+        unsafe {
+            libc::printf(
+                b"%s: %s\n\0".as_ptr() as *const libc::c_char,
+                target
+                    .as_ref()
+                    .map_or(b"mount\0".as_ptr() as *const libc::c_char, |t| t.inner()),
+                message.as_ptr() as *const libc::c_char,
+            );
+        }
+    }
+}
+
+fn remount_per_mount_flags(
+    target: &CStr,
+    per_mount_flags: libc::c_ulong,
+) -> Result<(), libc::c_int> {
+    unsafe {
+        // mount(NULL, "target", NULL, MS_REMOUNT | MS_BIND | MS_RDONLY, NULL)
+        if libc::mount(
+            core::ptr::null(),
+            target.inner(),
+            core::ptr::null(),
+            libc::MS_REMOUNT | libc::MS_BIND | per_mount_flags,
+            core::ptr::null(),
+        ) != 0
+        {
+            return Err(*libc::__errno_location());
+        }
+    }
+
+    Ok(())
+}
+
+/**
+ * Per-mount flags from the mount options field of a mountinfo line, e.g. rw,nosuid,relatime:
+ * no access time option stands for strictatime.
+ */
+fn mountinfo_per_mount_flags(options: &str) -> libc::c_ulong {
+    let mut atime = libc::MS_STRICTATIME;
+
+    let flags = options.split(',').fold(0, |flags, option| {
+        flags
+            | match option {
+                "ro" => libc::MS_RDONLY,
+                "nosuid" => libc::MS_NOSUID,
+                "nodev" => libc::MS_NODEV,
+                "noexec" => libc::MS_NOEXEC,
+                "nodiratime" => libc::MS_NODIRATIME,
+                "nosymfollow" => libc::MS_NOSYMFOLLOW,
+                "noatime" => {
+                    atime = libc::MS_NOATIME;
+                    0
+                }
+                "relatime" => {
+                    atime = libc::MS_RELATIME;
+                    0
+                }
+                _ => 0,
+            }
+    });
+
+    flags | atime
+}
+
+/**
+ * Apply the per-mount flags (ro, nosuid, nodev, noexec, access time, nosymfollow)
+ * to the mount at path and to every mount below it, on top of the flags each mount
+ * already has.
+ *
+ * The change is atomic with mount_setattr(2); on kernels older than 5.12 every mount
+ * listed in /proc/self/mountinfo below path is remounted in turn instead.
+ */
+pub fn set_tree_flags(path: &str, flags: &MountpointFlags) -> Result<(), libc::c_int> {
+    match set_attributes(path, &flags.mount_attr(), true) {
+        Err(libc::ENOSYS) => {}
+        result => return result,
+    }
+
+    // a mount missing from a truncated list would silently keep its flags
+    let mountinfo =
+        crate::read_complete_file(crate::MOUNTINFO_PATH, crate::MOUNTINFO_MAX_FILE_SIZE)?;
+    let mountinfo =
+        core::str::from_utf8(mountinfo.as_slice().unwrap_or(&[])).map_err(|_| libc::EINVAL)?;
+
+    let path = path.trim_end_matches('/');
+
+    let requested = flags.per_mount_flags();
+    let atime_flags = libc::MS_NOATIME | libc::MS_RELATIME | libc::MS_STRICTATIME;
+
+    // parents are listed before their children: remount them top-down
+    for line in mountinfo.lines() {
+        // the fifth field is the mount point, relative to the root of the process,
+        // the sixth one the per-mount options
+        let mut fields = line.split(' ').skip(4);
+        let (Some(mount_point), Some(mount_options)) = (fields.next(), fields.next()) else {
+            continue;
+        };
+
+        let mut mount_point_buf = Vec::<u8>::default();
+        unescape_octal(mount_point, &mut mount_point_buf)?;
+        let Ok(mount_point) = core::str::from_utf8(mount_point_buf.as_slice().unwrap_or(&[]))
+        else {
+            continue;
+        };
+
+        let below = mount_point
+            .strip_prefix(path)
+            .is_some_and(|rest| path.is_empty() || rest.is_empty() || rest.starts_with('/'));
+
+        if below {
+            // like mount_setattr(2), only add flags: the access time mode is replaced if given
+            let mut current = mountinfo_per_mount_flags(mount_options);
+            if requested & atime_flags != 0 {
+                current &= !atime_flags;
+            }
+
+            remount_per_mount_flags(&CStr::new(mount_point)?, current | requested)?;
+        }
+    }
+
+    Ok(())
+}
+
+/**
+ * Change the attributes of the mount at the given path with mount_setattr(2).
+ *
+ * @param recursive when true every mount below path is changed too, atomically
+ */
+pub fn set_attributes(
+    path: &str,
+    attr: &libc::mount_attr,
+    recursive: bool,
+) -> Result<(), libc::c_int> {
+    let path_str = CStr::new(path)?;
+
+    let flags = match recursive {
+        true => libc::AT_RECURSIVE,
+        false => 0,
+    };
+
+    unsafe {
+        /*
+         * On success, zero is returned. On error, -1 is returned, and errno
+         * is set to indicate the error.
+         */
+        if libc::syscall(
+            libc::SYS_mount_setattr,
+            libc::AT_FDCWD,
+            path_str.inner(),
+            flags as libc::c_uint,
+            attr as *const libc::mount_attr,
+            core::mem::size_of::<libc::mount_attr>(),
+        ) != 0
+        {
+            return Err(*libc::__errno_location());
+        }
+    }
+
+    Ok(())
+}
+
+/**
+ * ID-map a detached mount with mount_setattr(2), through a user namespace created
+ * for the mapping, setting the given attributes too.
+ *
+ * @param recursive when true every mount of the detached tree is mapped
+ */
+fn set_idmap_attributes(
+    mnt_fd: libc::c_int,
+    idmap: &IdMap,
+    mut attr: libc::mount_attr,
+    recursive: bool,
+) -> Result<(), libc::c_int> {
+    let userns_fd = idmap.user_namespace()?;
+
+    attr.attr_set |= libc::MOUNT_ATTR_IDMAP;
+    attr.userns_fd = userns_fd as u64;
+
+    let flags = match recursive {
+        true => libc::AT_EMPTY_PATH | libc::AT_RECURSIVE,
+        false => libc::AT_EMPTY_PATH,
+    };
+
+    let result = unsafe {
+        /*
+         * On success, zero is returned. On error, -1 is returned, and errno
+         * is set to indicate the error.
+         */
+        match libc::syscall(
+            libc::SYS_mount_setattr,
+            mnt_fd,
+            b"\0".as_ptr() as *const libc::c_char,
+            flags as libc::c_uint,
+            &attr as *const libc::mount_attr,
+            core::mem::size_of::<libc::mount_attr>(),
+        ) {
+            0 => Ok(()),
+            _ => Err(*libc::__errno_location()),
+        }
+    };
+
+    unsafe { libc::close(userns_fd) };
+
+    result
+}
+
 pub fn direct_detach(target: &str) -> Result<(), libc::c_int> {
     let target_str = CStr::new(target)?;
 
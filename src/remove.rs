@@ -0,0 +1,255 @@
+use crate::string::CStr;
+
+fn is_dot_or_dotdot(name: *const libc::c_char) -> bool {
+    unsafe {
+        (*name == b'.' as libc::c_char)
+            && ((*name.add(1) == 0)
+                || ((*name.add(1) == b'.' as libc::c_char) && (*name.add(2) == 0)))
+    }
+}
+
+/// Print the entry of the directory at path that could not be removed.
+fn report_failure(path: &CStr, name: *const libc::c_char, err: libc::c_int) {
+    unsafe {
+        libc::printf(
+            b"Failed to remove %s/%s: %d\n\0".as_ptr() as *const libc::c_char,
+            path.inner(),
+            name,
+            err as libc::c_int,
+        );
+    }
+}
+
+/**
+ * Remove every entry of the directory referred by dirfd, printing every entry
+ * that could not be removed: the last error is returned.
+ *
+ * The given file descriptor is consumed (closed) by this function.
+ *
+ * @param dirfd an open directory
+ * @param device when set entries that do not reside on that device are left untouched
+ * @param path the path of the directory without trailing slash, for messages only
+ */
+fn remove_dirfd_contents(
+    dirfd: libc::c_int,
+    device: Option<libc::dev_t>,
+    path: &CStr,
+) -> Result<(), libc::c_int> {
+    let dir = unsafe { libc::fdopendir(dirfd) };
+    if dir.is_null() {
+        let err = unsafe { *libc::__errno_location() };
+        unsafe { libc::close(dirfd) };
+        return Err(err);
+    }
+
+    let mut result = Ok(());
+
+    loop {
+        let entry = unsafe { libc::readdir(dir) };
+        if entry.is_null() {
+            break;
+        }
+
+        let name = unsafe { (*entry).d_name.as_ptr() };
+        if is_dot_or_dotdot(name) {
+            continue;
+        }
+
+        let mut fail = |err| {
+            report_failure(path, name, err);
+            result = Err(err);
+        };
+
+        let mut stat_buf: libc::stat = unsafe { core::mem::zeroed() };
+        if unsafe { libc::fstatat(dirfd, name, &mut stat_buf, libc::AT_SYMLINK_NOFOLLOW) } != 0 {
+            fail(unsafe { *libc::__errno_location() });
+            continue;
+        }
+
+        // never cross into a different filesystem (i.e. a mountpoint)
+        if let Some(dev) = device {
+            if stat_buf.st_dev != dev {
+                continue;
+            }
+        }
+
+        if (stat_buf.st_mode & libc::S_IFMT) == libc::S_IFDIR {
+            let subdir_fd = unsafe {
+                libc::openat(
+                    dirfd,
+                    name,
+                    libc::O_RDONLY | libc::O_DIRECTORY | libc::O_NOFOLLOW | libc::O_CLOEXEC,
+                )
+            };
+
+            if subdir_fd < 0 {
+                fail(unsafe { *libc::__errno_location() });
+                continue;
+            }
+
+            // the failures inside the subdirectory have been reported already
+            let subdir_name = unsafe { core::ffi::CStr::from_ptr(name) }.to_str();
+            let subdir_path = match subdir_name {
+                Ok(subdir_name) => CStr::concat(&[path.as_str(), "/", subdir_name]),
+                Err(_) => CStr::concat(&[path.as_str(), "/?"]),
+            };
+
+            let subdir_result = match subdir_path {
+                Ok(subdir_path) => remove_dirfd_contents(subdir_fd, device, &subdir_path),
+                Err(err) => {
+                    unsafe { libc::close(subdir_fd) };
+                    Err(err)
+                }
+            };
+
+            if let Err(err) = subdir_result {
+                result = Err(err);
+                continue;
+            }
+
+            if unsafe { libc::unlinkat(dirfd, name, libc::AT_REMOVEDIR) } != 0 {
+                fail(unsafe { *libc::__errno_location() });
+            }
+        } else if unsafe { libc::unlinkat(dirfd, name, 0) } != 0 {
+            fail(unsafe { *libc::__errno_location() });
+        }
+    }
+
+    unsafe { libc::closedir(dir) };
+
+    result
+}
+
+/**
+ * Recursively remove everything inside the given directory, leaving the (now empty)
+ * directory itself in place so that its mode, ownership and labels are preserved.
+ *
+ * Symlinks are never followed. A missing directory is not considered an error.
+ *
+ * @param path the directory to be emptied
+ * @param one_file_system when true entries residing on a filesystem
+ *  other than the one of path are skipped
+ */
+pub fn remove_directory_contents(path: &str, one_file_system: bool) -> Result<(), libc::c_int> {
+    let path_str = CStr::new(path)?;
+
+    let dirfd = unsafe {
+        libc::open(
+            path_str.inner(),
+            libc::O_RDONLY | libc::O_DIRECTORY | libc::O_NOFOLLOW | libc::O_CLOEXEC,
+        )
+    };
+
+    if dirfd < 0 {
+        return match unsafe { *libc::__errno_location() } {
+            libc::ENOENT => Ok(()),
+            err => Err(err),
+        };
+    }
+
+    remove_fd_contents(dirfd, one_file_system, path)
+}
+
+/**
+ * Recursively remove everything inside the directory referred by dirfd, as
+ * remove_directory_contents does: the directory may be unreachable by path.
+ *
+ * The given file descriptor is consumed (closed) by this function.
+ *
+ * @param path the path the directory had, for messages only
+ */
+pub fn remove_fd_contents(
+    dirfd: libc::c_int,
+    one_file_system: bool,
+    path: &str,
+) -> Result<(), libc::c_int> {
+    let path_str = match CStr::new(path.trim_end_matches('/')) {
+        Ok(path_str) => path_str,
+        Err(err) => {
+            unsafe { libc::close(dirfd) };
+            return Err(err);
+        }
+    };
+
+    let device = match one_file_system {
+        true => {
+            let mut stat_buf: libc::stat = unsafe { core::mem::zeroed() };
+            if unsafe { libc::fstat(dirfd, &mut stat_buf) } != 0 {
+                let err = unsafe { *libc::__errno_location() };
+                unsafe { libc::close(dirfd) };
+                return Err(err);
+            }
+
+            Some(stat_buf.st_dev)
+        }
+        false => None,
+    };
+
+    remove_dirfd_contents(dirfd, device, &path_str)
+}
+
+pub fn remove_file(path: &str) -> Result<(), libc::c_int> {
+    let path_str = CStr::new(path)?;
+
+    unsafe {
+        /*
+         * On success, zero is returned. On error, -1 is returned, and errno
+         * is set to indicate the error.
+         */
+        if libc::unlink(path_str.inner()) != 0 {
+            return Err(*libc::__errno_location());
+        }
+    }
+
+    Ok(())
+}
+
+/**
+ * Remove the file at the given absolute path and flush the removal to disk
+ * by syncing its parent directory.
+ */
+pub fn remove_file_durably(path: &str) -> Result<(), libc::c_int> {
+    remove_file(path)?;
+
+    let parent = match path.rfind('/') {
+        Some(0) => CStr::new("/")?,
+        Some(idx) => CStr::new(&path[..idx])?,
+        None => return Err(libc::EINVAL),
+    };
+
+    unsafe {
+        let fd = libc::open(
+            parent.inner(),
+            libc::O_RDONLY | libc::O_DIRECTORY | libc::O_CLOEXEC,
+        );
+        if fd < 0 {
+            return Err(*libc::__errno_location());
+        }
+
+        if libc::fsync(fd) != 0 {
+            let err = *libc::__errno_location();
+            libc::close(fd);
+            return Err(err);
+        }
+
+        libc::close(fd);
+    }
+
+    Ok(())
+}
+
+pub fn remove_directory(path: &str) -> Result<(), libc::c_int> {
+    let path_str = CStr::new(path)?;
+
+    unsafe {
+        /*
+         * On success, zero is returned. On error, -1 is returned, and errno
+         * is set to indicate the error.
+         */
+        if libc::rmdir(path_str.inner()) != 0 {
+            return Err(*libc::__errno_location());
+        }
+    }
+
+    Ok(())
+}
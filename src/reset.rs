@@ -0,0 +1,62 @@
+use crate::{
+    config::Config,
+    remove::{remove_directory_contents, remove_file_durably},
+};
+
+/**
+ * Check whether a factory reset has been requested via the trigger file on the early root.
+ */
+pub fn reset_requested() -> bool {
+    crate::check_file_exists(crate::RDRESET_PATH).unwrap_or(false)
+}
+
+/**
+ * Wipe every writable state directory declared in rdtab with the x-reset option.
+ *
+ * Every path is attempted even if a previous one failed: the first error is returned.
+ *
+ * @return the number of directories wiped
+ */
+pub fn factory_reset(config: &Config) -> Result<usize, libc::c_int> {
+    let mut result = Ok(());
+
+    let reset_paths = config.reset_paths()?;
+
+    for path in reset_paths.iter() {
+        #[cfg(feature = "trace")]
+        unsafe {
+            libc::printf(
+                b"Resetting %s\n\0".as_ptr() as *const libc::c_char,
+                path.inner(),
+            );
+        }
+
+        if let Err(err) = remove_directory_contents(path.as_str(), true) {
+            unsafe {
+                libc::printf(
+                    b"Failed to reset %s: %d\n\0".as_ptr() as *const libc::c_char,
+                    path.inner(),
+                    err as libc::c_int,
+                );
+            }
+
+            if result.is_ok() {
+                result = Err(err);
+            }
+        }
+    }
+
+    result.map(|_| reset_paths.len())
+}
+
+/**
+ * Remove the reset trigger and make sure the removal hits the disk,
+ * so that a completed reset is never performed a second time.
+ *
+ * This must only be called once factory_reset has succeeded and wiped at least one
+ * directory: an interrupted reset leaves the trigger in place and is simply repeated
+ * on the next boot.
+ */
+pub fn consume_trigger() -> Result<(), libc::c_int> {
+    remove_file_durably(crate::RDRESET_PATH)
+}
@@ -0,0 +1,178 @@
+use crate::{
+    btrfs::{create_snapshot, delete_subvolume},
+    string::CStr,
+    vector::Vec,
+};
+
+/// Maximum number of decimal digits of a snapshot number.
+const SNAPSHOT_NUMBER_MAX_DIGITS: usize = 20;
+
+/**
+ * Check whether the current boot is the first one after an update,
+ * as marked by the updater via the trigger file on the early root.
+ */
+pub fn snapshot_requested() -> bool {
+    crate::check_file_exists(crate::RDFIRSTBOOT_PATH).unwrap_or(false)
+}
+
+/**
+ * Number of snapshots to keep for each deployment: the trigger file
+ * may contain it, otherwise the default is used.
+ */
+pub fn snapshots_to_keep() -> usize {
+    let Ok(content) =
+        crate::read_whole_file(crate::RDFIRSTBOOT_PATH, crate::RDFIRSTBOOT_MAX_FILE_SIZE)
+    else {
+        return crate::DEFAULT_SNAPSHOTS_KEEP;
+    };
+
+    match core::str::from_utf8(content.as_slice().unwrap_or(&[]))
+        .map(|str| str.trim().parse::<usize>())
+    {
+        Ok(Ok(keep)) if keep > 0 => keep,
+        _ => crate::DEFAULT_SNAPSHOTS_KEEP,
+    }
+}
+
+/**
+ * Remove the first boot marker once the snapshot has been taken.
+ */
+pub fn consume_trigger() -> Result<(), libc::c_int> {
+    crate::remove::remove_file_durably(crate::RDFIRSTBOOT_PATH)
+}
+
+fn format_number(mut number: u64, buf: &mut [u8; SNAPSHOT_NUMBER_MAX_DIGITS]) -> &str {
+    let mut idx = buf.len();
+
+    loop {
+        idx -= 1;
+        buf[idx] = b'0' + (number % 10) as u8;
+        number /= 10;
+
+        if number == 0 {
+            break;
+        }
+    }
+
+    unsafe { core::str::from_utf8_unchecked(&buf[idx..]) }
+}
+
+fn create_directory(path: &CStr) -> Result<(), libc::c_int> {
+    unsafe {
+        /*
+         * On success, zero is returned. On error, -1 is returned, and errno
+         * is set to indicate the error.
+         */
+        if libc::mkdir(path.inner(), 0o700) != 0 {
+            return match *libc::__errno_location() {
+                libc::EEXIST => Ok(()),
+                err => Err(err),
+            };
+        }
+    }
+
+    Ok(())
+}
+
+/// Collect the numbers of the snapshots present in the given directory.
+fn list_snapshots(snapshots_dir: &CStr) -> Result<Vec<u64>, libc::c_int> {
+    let mut snapshots = Vec::<u64>::default();
+
+    let dir = unsafe { libc::opendir(snapshots_dir.inner()) };
+    if dir.is_null() {
+        return Err(unsafe { *libc::__errno_location() });
+    }
+
+    loop {
+        let entry = unsafe { libc::readdir(dir) };
+        if entry.is_null() {
+            break;
+        }
+
+        let name = unsafe { core::ffi::CStr::from_ptr((*entry).d_name.as_ptr()) };
+
+        // everything that is not a snapshot number (including . and ..) is ignored
+        let Some(number) = name.to_str().ok().and_then(|str| str.parse::<u64>().ok()) else {
+            continue;
+        };
+
+        if let Err(err) = snapshots.push(number) {
+            unsafe { libc::closedir(dir) };
+            return Err(err);
+        }
+    }
+
+    unsafe { libc::closedir(dir) };
+
+    Ok(snapshots)
+}
+
+/**
+ * Take a read-only snapshot of the given deployment subvolume, then prune
+ * the oldest snapshots of that deployment so that at most keep are retained.
+ *
+ * Snapshots are stored as /deployments/.snapshots/<name>/<number>,
+ * where a greater number means a more recent snapshot.
+ *
+ * @param deployment the path of the deployment subvolume, i.e. /deployments/<name>
+ * @param keep how many snapshots to retain, including the new one
+ */
+pub fn snapshot_deployment(deployment: &str, keep: usize) -> Result<(), libc::c_int> {
+    let (deployment, name) = crate::split_deployment_path(deployment).ok_or(libc::EINVAL)?;
+
+    let snapshots_root = CStr::concat(&[crate::DEPLOYMENTS_PATH, "/", crate::SNAPSHOTS_DIR])?;
+    create_directory(&snapshots_root)?;
+
+    let snapshots_dir = CStr::concat(&[snapshots_root.as_str(), "/", name])?;
+    create_directory(&snapshots_dir)?;
+
+    let mut snapshots = list_snapshots(&snapshots_dir)?;
+
+    let next = snapshots
+        .iter()
+        .max()
+        .map_or(0, |&latest| latest.saturating_add(1));
+
+    let mut buf = [0u8; SNAPSHOT_NUMBER_MAX_DIGITS];
+    let snapshot = CStr::concat(&[snapshots_dir.as_str(), "/", format_number(next, &mut buf)])?;
+
+    #[cfg(feature = "trace")]
+    unsafe {
+        libc::printf(
+            b"Creating snapshot %s\n\0".as_ptr() as *const libc::c_char,
+            snapshot.inner(),
+        );
+    }
+
+    create_snapshot(deployment, snapshot.as_str(), true)?;
+    snapshots.push(next)?;
+
+    // snapshot numbers are unique: delete them in increasing order
+    let mut last_pruned: Option<u64> = None;
+    for _ in 0..snapshots.len().saturating_sub(keep.max(1)) {
+        let Some(&oldest) = snapshots
+            .iter()
+            .filter(|&&n| last_pruned.is_none_or(|last| n > last))
+            .min()
+        else {
+            break;
+        };
+
+        last_pruned = Some(oldest);
+
+        let old_snapshot =
+            CStr::concat(&[snapshots_dir.as_str(), "/", format_number(oldest, &mut buf)])?;
+
+        if let Err(err) = delete_subvolume(old_snapshot.as_str()) {
+            unsafe {
+                libc::printf(
+                    b"Failed to prune snapshot %s: %d\n\0".as_ptr() as *const libc::c_char,
+                    old_snapshot.inner(),
+                    err as libc::c_int,
+                );
+            }
+        }
+    }
+
+    Ok(())
+}
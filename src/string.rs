@@ -1,3 +1,5 @@
+use crate::vector::Vec;
+
 #[derive(Debug)]
 pub struct CStr {
     alloc_sz: usize,
@@ -23,6 +25,32 @@ impl CStr {
         CStr::try_from(str.as_bytes())
     }
 
+    /// Build a new string by joining all the given parts, without separators.
+    pub fn concat(parts: &[&str]) -> Result<Self, libc::c_int> {
+        let alloc_sz = parts.iter().map(|part| part.len()).sum::<usize>() + 1;
+        let data = unsafe { libc::malloc(alloc_sz) } as *mut libc::c_char;
+
+        if data.is_null() {
+            return Err(libc::ENOMEM);
+        }
+
+        let mut offset = 0;
+        for part in parts {
+            unsafe {
+                let _ = libc::memcpy(
+                    data.add(offset) as *mut libc::c_void,
+                    part.as_ptr() as *const libc::c_void,
+                    part.len(),
+                );
+            }
+            offset += part.len();
+        }
+
+        unsafe { *data.add(offset) = 0 };
+
+        Ok(Self { alloc_sz, data })
+    }
+
     pub fn strlen(&self) -> usize {
         /*
         for i in 0..self.alloc_sz {
@@ -79,3 +107,35 @@ impl TryFrom<&[u8]> for CStr {
         Ok(Self { alloc_sz, data })
     }
 }
+
+/**
+ * Append field to buf decoding the escapes used by fstab and /proc/self/mountinfo:
+ * a backslash followed by three octal digits stands for that byte (e.g. \040 for a space).
+ * Any other backslash is kept as it is.
+ */
+pub fn unescape_octal(field: &str, buf: &mut Vec<u8>) -> Result<(), libc::c_int> {
+    let bytes = field.as_bytes();
+
+    let mut idx = 0;
+    while idx < bytes.len() {
+        let octal = bytes
+            .get(idx + 1..idx + 4)
+            .filter(|digits| {
+                bytes[idx] == b'\\' && digits.iter().all(|d| (b'0'..=b'7').contains(d))
+            })
+            .and_then(|digits| u8::from_str_radix(core::str::from_utf8(digits).ok()?, 8).ok());
+
+        match octal {
+            Some(byte) => {
+                buf.push(byte)?;
+                idx += 4;
+            }
+            None => {
+                buf.push(bytes[idx])?;
+                idx += 1;
+            }
+        }
+    }
+
+    Ok(())
+}
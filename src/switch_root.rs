@@ -1,17 +1,68 @@
 use crate::{
     change_dir::{chdir, chroot},
+    command::Command,
     mount::{direct_detach, MountFlag, Mountpoint, MountpointFlags},
+    remove::remove_fd_contents,
     string::CStr,
 };
 
-#[cfg(target_arch = "arm")]
-const SYS_PIVOT_ROOT: libc::c_long = 218;
+/// Filesystem types of the initial rootfs, from linux/magic.h.
+const RAMFS_MAGIC: u32 = 0x858458f6;
+const TMPFS_MAGIC: u32 = 0x01021994;
 
-#[cfg(target_arch = "aarch64")]
-const SYS_PIVOT_ROOT: libc::c_long = 41;
+/**
+ * Whether / is the root of the mount tree, i.e. it was not mounted over another
+ * filesystem, according to /proc/self/mountinfo: only the root mount is its own parent.
+ */
+fn root_is_first_mount() -> Result<bool, libc::c_int> {
+    // a truncated mountinfo could miss the last mount on / and mislead the detection
+    let mountinfo =
+        crate::read_complete_file(crate::MOUNTINFO_PATH, crate::MOUNTINFO_MAX_FILE_SIZE)?;
+    let mountinfo =
+        core::str::from_utf8(mountinfo.as_slice().unwrap_or(&[])).map_err(|_| libc::EINVAL)?;
+
+    // mounts stacked on / are listed after the one they hide
+    let root = mountinfo
+        .lines()
+        .map(|line| line.split(' '))
+        .filter_map(|mut fields| {
+            let id = fields.next()?;
+            let parent_id = fields.next()?;
+
+            (fields.nth(2)? == "/").then_some((id, parent_id))
+        })
+        .next_back();
+
+    match root {
+        Some((id, parent_id)) => Ok(id == parent_id),
+        None => Err(libc::ENOENT),
+    }
+}
 
-#[cfg(target_arch = "x86_64")]
-const SYS_PIVOT_ROOT: libc::c_long = 155;
+/**
+ * Detect whether / is the initramfs: the kernel's initial rootfs, a ramfs or a tmpfs
+ * at the root of the mount tree, that pivot_root(2) cannot move away.
+ *
+ * A ramfs or tmpfs mounted over the rootfs (e.g. by an initrd) is not the initramfs.
+ * When /proc/self/mountinfo cannot be read the filesystem type alone decides, but
+ * one too large to be read whole is an error rather than a guess.
+ */
+pub fn root_is_initramfs() -> Result<bool, libc::c_int> {
+    let mut statfs_buf: libc::statfs = unsafe { core::mem::zeroed() };
+
+    if unsafe { libc::statfs(b"/\0".as_ptr() as *const libc::c_char, &mut statfs_buf) } != 0 {
+        return Err(unsafe { *libc::__errno_location() });
+    }
+
+    // f_type has a different width on each architecture
+    match statfs_buf.f_type as u32 {
+        RAMFS_MAGIC | TMPFS_MAGIC => match root_is_first_mount() {
+            Err(libc::EFBIG) => Err(libc::EFBIG),
+            result => Ok(result.unwrap_or(true)),
+        },
+        _ => Ok(false),
+    }
+}
 
 pub fn pivot_root(new_root: &str, put_old: &str) -> Result<(), libc::c_int> {
     let new_root_str = CStr::new(new_root)?;
@@ -22,7 +73,12 @@ pub fn pivot_root(new_root: &str, put_old: &str) -> Result<(), libc::c_int> {
          * On success, zero is returned. On error, -1 is returned, and errno
          * is set to indicate the error.
          */
-        if libc::syscall(SYS_PIVOT_ROOT, new_root_str.inner(), put_old_str.inner()) != 0 {
+        if libc::syscall(
+            libc::SYS_pivot_root,
+            new_root_str.inner(),
+            put_old_str.inner(),
+        ) != 0
+        {
             return Err(*libc::__errno_location());
         }
     }
@@ -31,21 +87,7 @@ pub fn pivot_root(new_root: &str, put_old: &str) -> Result<(), libc::c_int> {
 }
 
 pub fn execute(program: &str) -> Result<(), libc::c_int> {
-    let program_str = CStr::new(program)?;
-
-    let argv: [*const libc::c_char; 2] = [program_str.inner(), core::ptr::null()];
-
-    unsafe {
-        /*
-         * On success, zero is returned. On error, -1 is returned, and errno
-         * is set to indicate the error.
-         */
-        if libc::execve(program_str.inner(), argv.as_ptr(), core::ptr::null()) != 0 {
-            return Err(*libc::__errno_location());
-        }
-    }
-
-    Ok(())
+    Command::new(program)?.execute()
 }
 
 fn initramfs_switch_root(new_root: &str) -> Result<(), libc::c_int> {
@@ -58,6 +100,7 @@ fn initramfs_switch_root(new_root: &str) -> Result<(), libc::c_int> {
         None,
     )?
     .mount(&None)
+    .map_err(|err| err.errno())
     {
         unsafe {
             libc::printf(
@@ -90,6 +133,147 @@ fn initramfs_switch_root(new_root: &str) -> Result<(), libc::c_int> {
     Ok(())
 }
 
+fn device_of(path: &CStr) -> Result<libc::dev_t, libc::c_int> {
+    let mut stat_buf: libc::stat = unsafe { core::mem::zeroed() };
+
+    if unsafe { libc::stat(path.inner(), &mut stat_buf) } != 0 {
+        return Err(unsafe { *libc::__errno_location() });
+    }
+
+    Ok(stat_buf.st_dev)
+}
+
+/**
+ * The directory that switch_root turns into the new root.
+ *
+ * On an initramfs it is where the root device is mounted, that may differ from
+ * SYSROOT when a rdtab entry mounts rootdev elsewhere; pivot_root always uses SYSROOT.
+ *
+ * @param initramfs
+ * @param rootfs_target where the root device is mounted
+ */
+pub fn new_root_path(initramfs: bool, rootfs_target: &str) -> &str {
+    match initramfs {
+        true => rootfs_target,
+        false => crate::SYSROOT,
+    }
+}
+
+/**
+ * Move a mount of the early root (e.g. /dev) to the same path below the new root,
+ * so that it survives the root switch.
+ *
+ * Nothing is done when path is not a mount point, when something is mounted at
+ * the destination already (e.g. by rdtab) or when the new root lacks the destination
+ * directory: the latter is reported on the console.
+ *
+ * @param path an absolute path on the early root
+ * @param new_root where the new root is mounted
+ * @return whether the mount was moved
+ */
+pub fn move_into_new_root(path: &str, new_root: &str) -> Result<bool, libc::c_int> {
+    let path_str = CStr::new(path)?;
+    let new_root_str = CStr::new(new_root)?;
+    let target_str = CStr::concat(&[new_root.trim_end_matches('/'), path])?;
+
+    let source_mounted = match device_of(&path_str) {
+        Ok(dev) => dev != device_of(&CStr::new("/")?)?,
+        Err(libc::ENOENT) => false,
+        Err(err) => return Err(err),
+    };
+
+    if !source_mounted {
+        return Ok(false);
+    }
+
+    match device_of(&target_str) {
+        Ok(dev) if dev != device_of(&new_root_str)? => return Ok(false),
+        Ok(_) => {}
+        Err(libc::ENOENT) => {
+            unsafe {
+                libc::printf(
+                    b"Not moving %s: %s does not exist in the new root\n\0".as_ptr()
+                        as *const libc::c_char,
+                    path_str.inner(),
+                    target_str.inner(),
+                );
+            }
+
+            return Ok(false);
+        }
+        Err(err) => return Err(err),
+    }
+
+    Mountpoint::new(
+        Some(path),
+        target_str.as_str(),
+        None,
+        MountpointFlags::new(&[MountFlag::Move]),
+        None,
+    )?
+    .mount(&None)
+    .map_err(|err| err.errno())?;
+
+    Ok(true)
+}
+
+/// Open the root directory, to reach the initramfs once the new root is moved over it.
+fn open_old_root() -> Result<libc::c_int, libc::c_int> {
+    let fd = unsafe {
+        libc::open(
+            b"/\0".as_ptr() as *const libc::c_char,
+            libc::O_RDONLY | libc::O_DIRECTORY | libc::O_CLOEXEC,
+        )
+    };
+
+    match fd {
+        fd if fd < 0 => Err(unsafe { *libc::__errno_location() }),
+        fd => Ok(fd),
+    }
+}
+
+/**
+ * Delete the contents of the initramfs, that are unreachable once the new root is
+ * moved over it but would keep holding memory, like util-linux switch_root does.
+ *
+ * This happens only once the switch succeeded, through a descriptor opened on the
+ * initramfs before: a failed switch leaves the initramfs, and its recovery shell, intact.
+ *
+ * Mounts, the new root included, are never crossed. Nothing is deleted when the
+ * new root resides on the initramfs itself (i.e. it is a bind mount of one of its
+ * directories).
+ *
+ * @param old_root_fd the root directory of the initramfs, consumed (closed) by this function
+ */
+fn remove_initramfs_contents(old_root_fd: libc::c_int) -> Result<(), libc::c_int> {
+    let mut stat_buf: libc::stat = unsafe { core::mem::zeroed() };
+
+    if unsafe { libc::fstat(old_root_fd, &mut stat_buf) } != 0 {
+        let err = unsafe { *libc::__errno_location() };
+        unsafe { libc::close(old_root_fd) };
+        return Err(err);
+    }
+
+    // after the switch / is the new root
+    match CStr::new("/").and_then(|root| device_of(&root)) {
+        Ok(dev) if dev != stat_buf.st_dev => remove_fd_contents(old_root_fd, true, "/"),
+        result => {
+            unsafe { libc::close(old_root_fd) };
+
+            if result.is_ok() {
+                unsafe {
+                    libc::printf(
+                        b"Keeping the initramfs: the new root resides on it\n\0".as_ptr()
+                            as *const libc::c_char,
+                    );
+                }
+            }
+
+            result.map(|_| ())
+        }
+    }
+}
+
 fn initrd_switch_root(put_old: &str) -> Result<(), libc::c_int> {
     if let Err(err) = pivot_root(".", put_old) {
         unsafe {
@@ -133,21 +317,20 @@ fn initrd_switch_root(put_old: &str) -> Result<(), libc::c_int> {
  * and sys_pivot_root cannot be used: remount the root device as / instead.
  *
  * @param initramfs
+ * @param free_initramfs whether the contents of the initramfs are to be deleted
  * @param new_root where the root device is mounted:
  *  on an initramfs sys_pivot_root will NOT be performed
  * @param put_old the second parameter for sys_pivot_root: must be expressed relative to new_root
- * @param program the init program to be execve'd on the new root: must be expressed relative to new_root
+ * @param init the init command to be execve'd on the new root: its program must be expressed relative to new_root
  */
 pub fn switch_root(
     initramfs: bool,
+    free_initramfs: bool,
     new_root: &str,
     put_old: &str,
-    program: &str,
+    init: &Command,
 ) -> Result<(), libc::c_int> {
-    if let Err(err) = chdir(match initramfs {
-        true => new_root,
-        false => crate::SYSROOT,
-    }) {
+    if let Err(err) = chdir(new_root_path(initramfs, new_root)) {
         unsafe {
             libc::printf(
                 b"Failed to chdir to the new rootfs: %d\n\0".as_ptr() as *const libc::c_char,
@@ -168,14 +351,50 @@ pub fn switch_root(
         },
     };
 
-    match initramfs {
+    // a failure to free the initramfs only wastes memory: keep booting
+    let old_root_fd = match initramfs && free_initramfs {
+        true => open_old_root()
+            .inspect_err(|&err| unsafe {
+                libc::printf(
+                    b"Failed to open the initramfs to remove its contents: %d\n\0".as_ptr()
+                        as *const libc::c_char,
+                    err as libc::c_int,
+                );
+            })
+            .ok(),
+        false => None,
+    };
+
+    let switched = match initramfs {
         // follow the switch_root procedure for initramfs
-        true => initramfs_switch_root(new_root)?,
+        true => initramfs_switch_root(new_root),
         // follow the pivot_root procedure for initrd
-        false => initrd_switch_root(put_old)?,
+        false => initrd_switch_root(put_old),
     };
 
-    if let Err(err) = execute(program) {
+    if let Err(err) = switched {
+        if let Some(old_root_fd) = old_root_fd {
+            unsafe { libc::close(old_root_fd) };
+        }
+
+        return Err(err);
+    }
+
+    if let Some(old_root_fd) = old_root_fd {
+        if let Err(err) = remove_initramfs_contents(old_root_fd) {
+            unsafe {
+                libc::printf(
+                    b"Failed to remove the initramfs contents: %d\n\0".as_ptr()
+                        as *const libc::c_char,
+                    err as libc::c_int,
+                );
+            }
+        }
+    }
+
+    if let Err(err) = init.execute() {
+        let program = init.program();
+
         let Ok(prog_name) = CStr::new(program) else {
             unsafe {
                 libc::printf(
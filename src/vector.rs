@@ -276,6 +276,14 @@ impl<T> Vec<T> {
             true => None,
         }
     }
+
+    pub fn as_mut_slice(&mut self) -> Option<&mut [T]> {
+        // Safety: see as_slice, the mutable borrow of self guarantees exclusive access.
+        match self.empty() {
+            false => Some(unsafe { core::slice::from_raw_parts_mut(self.ptr, self.length) }),
+            true => None,
+        }
+    }
 }
 
 impl<T> Drop for Vec<T> {
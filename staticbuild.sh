@@ -1,4 +1,5 @@
 #!/bin/sh
 
-# Builds the software statically
-cargo build --release --target=x86_64-unknown-linux-musl
\ No newline at end of file
+# Builds the software statically, for the given target triple (x86_64 by default),
+# e.g. ./staticbuild.sh riscv64gc-unknown-linux-musl
+cargo build --release --target="${1:-x86_64-unknown-linux-musl}"